@@ -19,7 +19,7 @@ pub struct Advice {
     pub reconnect: Reconnect,
     pub timeout: Option<u32>,
     pub interval: Option<u32>,
-    #[serde(rename = "kebab-case")]
+    #[serde(rename = "multiple-clients")]
     pub multiple_clients: Option<bool>,
     pub hosts: Option<Vec<String>>,
 }
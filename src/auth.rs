@@ -0,0 +1,27 @@
+/// The way the client authenticates against the cometd server. This controls
+/// the header sent along with every request.
+#[derive(PartialEq, Debug, Clone, Default)]
+pub enum AuthScheme {
+    /// Sends `Authorization: OAuth {access_token}`. This is the default.
+    #[default]
+    OAuth,
+    /// Sends `Authorization: Bearer {access_token}`.
+    Bearer,
+    /// Sends `Authorization: Basic {base64(user:pass)}`.
+    Basic { user: String, pass: String },
+    /// Sends `{header_name}: {value}`.
+    Custom { header_name: String, value: String },
+    /// Does not send any authentication header.
+    None,
+}
+
+impl AuthScheme {
+    /// Returns whether the scheme sends credentials, given the access token of the client.
+    pub(crate) fn has_credentials(&self, access_token: &str) -> bool {
+        match self {
+            AuthScheme::OAuth | AuthScheme::Bearer => !access_token.is_empty(),
+            AuthScheme::Basic { .. } | AuthScheme::Custom { .. } => true,
+            AuthScheme::None => false,
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use crate::error::{Error, ErrorKind};
+
+/// The meta channel used to open a session.
+pub const HANDSHAKE: &str = "/meta/handshake";
+/// The meta channel used to poll for messages and keep the session alive.
+pub const CONNECT: &str = "/meta/connect";
+/// The meta channel used to close a session.
+pub const DISCONNECT: &str = "/meta/disconnect";
+/// The meta channel used to subscribe to channels.
+pub const SUBSCRIBE: &str = "/meta/subscribe";
+/// The meta channel used to unsubscribe from channels.
+pub const UNSUBSCRIBE: &str = "/meta/unsubscribe";
+
+/// What a channel name is used for, since publishing and subscribing do not
+/// accept the same names.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ChannelKind {
+    /// The channel is subscribed or unsubscribed to. Wildcards are allowed as
+    /// the last segment.
+    Subscribe,
+    /// A message is published on the channel. Wildcards and meta channels are
+    /// not allowed.
+    Publish,
+}
+
+/// Returns whether the channel is a meta channel, that is one used by the protocol itself
+/// rather than to exchange application messages.
+pub fn is_meta(channel: &str) -> bool {
+    channel.split('/').nth(1) == Some("meta")
+}
+
+/// Checks that a channel name is valid for the given use.
+///
+/// # Errors
+///
+/// Will return an error if the name is empty, does not start with a `/`, contains
+/// an empty segment or a misplaced wildcard, or if a meta channel is used to publish.
+pub fn validate_channel(channel: &str, kind: ChannelKind) -> Result<(), Error> {
+    let invalid = |reason: &str| {
+        Err(Error::new(
+            ErrorKind::Parse,
+            &format!("Invalid channel {:?}: {}", channel, reason),
+        ))
+    };
+
+    if channel.is_empty() {
+        return invalid("the name is empty");
+    }
+    if !channel.starts_with('/') {
+        return invalid("the name must start with a /");
+    }
+    let segments = channel[1..].split('/').collect::<Vec<_>>();
+    if segments.iter().any(|segment| segment.is_empty()) {
+        return invalid("the name contains an empty segment");
+    }
+    if kind == ChannelKind::Publish && is_meta(channel) {
+        return invalid("cannot publish on a meta channel");
+    }
+    for (i, segment) in segments.iter().enumerate() {
+        if !segment.contains('*') {
+            continue;
+        }
+        if kind == ChannelKind::Publish {
+            return invalid("cannot publish on a wildcard channel");
+        }
+        if (*segment != "*" && *segment != "**") || i != segments.len() - 1 {
+            return invalid("wildcards are only allowed as the last segment");
+        }
+    }
+    Ok(())
+}
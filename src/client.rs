@@ -1,21 +1,121 @@
-use reqwest::{Client as ReqwestClient, Response as ReqwestReponse, Url};
+use reqwest::blocking::{Client as ReqwestClient, RequestBuilder};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, CONTENT_TYPE};
+use reqwest::{StatusCode, Url};
 use serde::Serialize;
-use std::time::Duration;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use crate::advice::{Advice, Reconnect};
-use crate::config::{COMETD_SUPPORTED_TYPES, COMETD_VERSION};
-use crate::error::Error;
-use crate::response::{ErroredResponse, Response};
+use crate::auth::AuthScheme;
+use crate::channel::{self, validate_channel, ChannelKind};
+use crate::error::{Error, ErrorKind};
+use crate::metrics::{self, Metrics};
+use crate::response::{
+    check_known_fields, BasicResponse, DeliveryResponse, ErroredResponse, HandshakeResponse,
+    Response, Subscription,
+};
+use crate::state::ClientState;
+#[cfg(feature = "websocket")]
+use crate::transport::websocket::{self, WebSocket};
+use crate::transport::Transport;
+
+#[cfg(feature = "async")]
+pub mod async_client;
+mod builder;
+mod session;
+
+pub use builder::ClientBuilder;
+pub use session::Session;
+use session::{SessionState, SharedSession};
+
+/// A callback invoked every time the client reconnects following an advice from the server.
+pub type ReconnectCallback = Box<dyn Fn() + Send + Sync>;
+/// A callback invoked every time the client successfully handshakes with the server.
+pub type HandshakeCallback = Box<dyn Fn(&HandshakeResponse) + Send + Sync>;
+/// A callback invoked when no message has been delivered for longer than the idle timeout,
+/// with the time elapsed since the last one.
+pub type IdleCallback = Box<dyn Fn(Duration) + Send + Sync>;
+/// A function turning the messages sent to the server into JSON.
+pub type Serializer = Box<dyn Fn(&serde_json::Value) -> Result<String, Error> + Send + Sync>;
+/// A function returning the `ext` to add to a message sent on the given channel, if any.
+pub type ExtProvider = Box<dyn Fn(&str) -> Option<serde_json::Value> + Send + Sync>;
 
 /// The cometd client.
 pub struct Client {
     http_client: ReqwestClient,
     base_url: Url,
     access_token: String,
+    auth: AuthScheme,
+    require_https: bool,
     client_id: Option<String>,
+    state: ClientState,
     cookies: Vec<String>,
-    max_retries: i8,
-    actual_retries: i8,
+    headers: HeaderMap,
+    timeout: Option<Duration>,
+    timeout_override: Option<Duration>,
+    message_id: u64,
+    handshake_ext: Option<serde_json::Value>,
+    replay: BTreeMap<String, i64>,
+    endpoints: HashMap<String, String>,
+    subscriptions: HashSet<String>,
+    pending_subscriptions: Vec<String>,
+    version: String,
+    minimum_version: Option<String>,
+    advice: Option<Advice>,
+    retried_request: Option<serde_json::Value>,
+    auto_disconnect: bool,
+    rehandshake_on_unknown_client: bool,
+    follow_advice_hosts: bool,
+    log_bodies: bool,
+    strict_parsing: bool,
+    partial_results: bool,
+    partial_errors: Vec<Error>,
+    pending_messages: VecDeque<Response>,
+    max_messages_per_poll: Option<usize>,
+    buffer_size: usize,
+    recent_messages: VecDeque<Response>,
+    treat_timeout_as_empty: bool,
+    transport: Transport,
+    connection_type: Option<String>,
+    supported_connection_types: Vec<String>,
+    negotiated_connection_type: Option<String>,
+    server_supported_types: Vec<String>,
+    #[cfg(feature = "websocket")]
+    websocket: Mutex<Option<WebSocket>>,
+    #[cfg(feature = "websocket")]
+    websocket_unrelated: Mutex<Vec<String>>,
+    #[cfg(feature = "websocket")]
+    websocket_unsupported_option: Option<&'static str>,
+    max_transport_retries: i8,
+    transport_retries: i8,
+    max_advice_reconnects: i8,
+    advice_reconnects: i8,
+    retries_started: Instant,
+    max_reconnect_duration: Option<Duration>,
+    backoff: Option<Backoff>,
+    default_interval: Duration,
+    max_network_delay: Duration,
+    on_reconnect: Option<ReconnectCallback>,
+    on_handshake: Option<HandshakeCallback>,
+    idle_timeout: Option<Duration>,
+    on_idle: Option<IdleCallback>,
+    serializer: Option<Serializer>,
+    ext_provider: Option<ExtProvider>,
+    timesync: bool,
+    ack_extension: bool,
+    ack_id: Option<i64>,
+    clock_offset: Option<i64>,
+    network_lag: Option<u32>,
+    cancelled: Option<Arc<AtomicBool>>,
+    session: Option<Arc<SharedSession>>,
+    dry_run: bool,
+    dry_run_requests: Mutex<Vec<String>>,
+    metrics: Arc<dyn Metrics>,
 }
 
 #[derive(Serialize)]
@@ -23,7 +123,12 @@ pub struct Client {
 struct HandshakePayload<'a> {
     channel: &'a str,
     version: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    minimum_version: Option<&'a str>,
     supported_connection_types: Vec<&'a str>,
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ext: Option<&'a serde_json::Value>,
 }
 
 #[derive(Serialize)]
@@ -32,6 +137,9 @@ struct ConnectPayload<'a> {
     channel: &'a str,
     client_id: &'a str,
     connection_type: &'a str,
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ext: Option<serde_json::Value>,
 }
 
 #[derive(Serialize)]
@@ -39,14 +147,21 @@ struct ConnectPayload<'a> {
 struct DisconnectPayload<'a> {
     channel: &'a str,
     client_id: &'a str,
+    id: String,
 }
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
-struct SubscribeTopicPayload<'a> {
+struct SubscribeTopicPayload<'a, S>
+where
+    S: Serialize,
+{
     pub channel: &'a str,
     pub client_id: &'a str,
-    pub subscription: &'a str,
+    pub subscription: S,
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ext: Option<serde_json::Value>,
 }
 
 #[derive(Serialize)]
@@ -58,6 +173,400 @@ where
     pub channel: &'a str,
     pub client_id: &'a str,
     pub data: T,
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ext: Option<&'a serde_json::Value>,
+}
+
+/// The raw answer of the cometd server, whatever the transport used.
+struct RawResponse {
+    body: String,
+    cookies: Vec<String>,
+}
+
+/// The body returned by the cometd server, either made of errored responses
+/// or of successful ones.
+enum ParsedBody {
+    Errored(Vec<ErroredResponse>),
+    Successful(Vec<Response>),
+}
+
+/// The request sent next when following an advice from the server.
+#[derive(Clone, Copy)]
+enum Step {
+    Connect,
+    Handshake,
+}
+
+/// The client id the handshakes are answered with in dry run mode.
+const DRY_RUN_CLIENT_ID: &str = "dry-run";
+
+/// How often a cancellable request checks whether it was cancelled.
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The maximum number of characters of an unparsable or errored body included in the error
+/// message.
+const MAX_BODY_EXCERPT_LEN: usize = 256;
+
+/// Parses the body returned by the cometd server. Errored responses are tried
+/// first since they would also match a basic response otherwise. The body is only
+/// logged, and included in the error message, if `log_bodies` is set.
+fn parse_body(body: &str, log_bodies: bool) -> Result<ParsedBody, Error> {
+    if log_bodies {
+        log::debug!("Received response from cometd server: {:?}", body);
+    } else {
+        log::debug!(
+            "Received response of {} bytes from cometd server",
+            body.len()
+        );
+    }
+    if let Ok(resps) = serde_json::from_str::<Vec<ErroredResponse>>(body) {
+        return Ok(ParsedBody::Errored(resps));
+    }
+    let err = match serde_json::from_str::<Vec<Response>>(body) {
+        Ok(resps) => return Ok(ParsedBody::Successful(resps)),
+        Err(err) => err,
+    };
+    // Some servers send a single message without the enclosing array.
+    if let Ok(resp) = serde_json::from_str::<ErroredResponse>(body) {
+        return Ok(ParsedBody::Errored(vec![resp]));
+    }
+    if let Ok(resp) = serde_json::from_str::<Response>(body) {
+        return Ok(ParsedBody::Successful(vec![resp]));
+    }
+
+    if !log_bodies {
+        // The parsing error itself may quote the body, so only its position is kept.
+        let message = format!(
+            "Could not parse response (line {}, column {})",
+            err.line(),
+            err.column()
+        );
+
+        return Err(Error::new(ErrorKind::Parse, &message).with_body(body));
+    }
+    log::error!(
+        "Handle response failed with the following server response: {:?}",
+        body
+    );
+    let message = format!("Could not parse response ({}): {}", err, excerpt(body));
+
+    Err(Error::with_source(ErrorKind::Parse, &message, err).with_body(body))
+}
+
+/// Returns the beginning of a body, to be included in an error message.
+fn excerpt(body: &str) -> String {
+    let mut excerpt = body.chars().take(MAX_BODY_EXCERPT_LEN).collect::<String>();
+
+    if excerpt.len() < body.len() {
+        excerpt.push_str("...");
+    }
+    excerpt
+}
+
+/// Checks that the reply to a meta message carries the id of the message it answers,
+/// if the server provided one. Other messages, such as deliveries, carry their own ids.
+fn check_id(channel: &str, id: Option<&str>, expected_id: &str) -> Result<(), Error> {
+    match id {
+        Some(id) if channel::is_meta(channel) && id != expected_id => Err(Error::new(
+            ErrorKind::Protocol,
+            &format!(
+                "Response id {} does not match the request id {}",
+                id, expected_id
+            ),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the request to send next to follow an advice of the server, or an error if it
+/// advised not to reconnect nor handshake.
+fn advised_step(advice: &Advice, error: Option<&str>) -> Result<Step, Error> {
+    match advice.reconnect {
+        Reconnect::Handshake => Ok(Step::Handshake),
+        Reconnect::Retry => Ok(Step::Connect),
+        Reconnect::None => {
+            log::debug!("Not retrying because the server answered not to reconnect nor handshake");
+            Err(Error::new(
+                ErrorKind::ServerAdvisedStop,
+                error.unwrap_or("Service advised not to reconnect nor handshake"),
+            ))
+        }
+    }
+}
+
+/// Returns the advice to follow to recover from an error of the server, or the error to
+/// return if it cannot be recovered from. A `402::Unknown client` error without advice is
+/// recovered from by handshaking again if `rehandshake_on_unknown_client` is set.
+fn recovery_advice(
+    resp: &ErroredResponse,
+    version: &str,
+    rehandshake_on_unknown_client: bool,
+) -> Result<Advice, Error> {
+    if resp.channel == channel::HANDSHAKE && resp.error_parts().0 == Some(406) {
+        let message = match resp.minimum_version {
+            Some(ref minimum_version) => format!(
+                "The server does not support version {}, it requires at least version {}",
+                version, minimum_version
+            ),
+            None => format!("The server does not support version {}", version),
+        };
+
+        return Err(Error::new(ErrorKind::VersionNegotiation, &message));
+    }
+    match resp.advice {
+        Some(ref advice) => Ok(advice.clone()),
+        None if rehandshake_on_unknown_client && resp.error_parts().0 == Some(402) => {
+            log::debug!("Handshaking again since the server does not know the client");
+            Ok(Advice {
+                reconnect: Reconnect::Handshake,
+                timeout: None,
+                interval: None,
+                multiple_clients: None,
+                hosts: None,
+            })
+        }
+        None => {
+            log::debug!("Not retrying because the server did not provide advice");
+            Err(Error::new(ErrorKind::Protocol, &resp.error))
+        }
+    }
+}
+
+/// Prepares a request the server advised to retry to be sent again, with the current client
+/// id since a handshake may have happened in between. Meta messages get a new id since their
+/// replies are checked against the last one, while publishes keep theirs so they can still be
+/// tracked.
+fn prepare_resend(
+    body: &mut serde_json::Value,
+    client_id: Option<&str>,
+    mut next_message_id: impl FnMut() -> String,
+) {
+    let messages = match body {
+        serde_json::Value::Array(ref mut messages) => messages.iter_mut().collect(),
+        message => vec![message],
+    };
+
+    for message in messages {
+        if message["channel"].as_str().is_some_and(channel::is_meta) {
+            message["id"] = next_message_id().into();
+        }
+        if let Some(client_id) = client_id {
+            message["clientId"] = client_id.into();
+        }
+    }
+}
+
+/// Returns the channel of a single message, or `None` for a batch.
+fn body_channel(body: &serde_json::Value) -> Option<&str> {
+    body.get("channel").and_then(serde_json::Value::as_str)
+}
+
+/// Keeps the cookies set by a response, replacing the stored ones with the same name.
+fn merge_cookies(stored: &mut Vec<String>, cookies: Vec<String>) {
+    for cookie in cookies {
+        let name = cookie.split('=').next().unwrap_or_default().to_owned();
+        match stored
+            .iter_mut()
+            .find(|stored| stored.split('=').next() == Some(name.as_str()))
+        {
+            Some(stored) => *stored = cookie,
+            None => stored.push(cookie),
+        }
+    }
+}
+
+/// Checks that the server did not reject the subscription to the given channel. A rejection
+/// without advice is not an error response since it may lack the error message.
+fn check_subscription(resps: &[Response], subscription: &str) -> Result<(), Error> {
+    let rejected = resps.iter().find_map(|resp| match resp {
+        Response::Basic(resp) if resp.channel == channel::SUBSCRIBE && !resp.successful => {
+            let matches = match resp.subscription {
+                Some(Subscription::One(ref channel)) => channel == subscription,
+                Some(Subscription::Many(ref channels)) => {
+                    channels.iter().any(|c| c == subscription)
+                }
+                None => true,
+            };
+            matches.then_some(resp)
+        }
+        _ => None,
+    });
+
+    match rejected {
+        Some(resp) => Err(Error::new(
+            ErrorKind::Protocol,
+            resp.error.as_deref().unwrap_or(&format!(
+                "The server did not accept the subscription to {}",
+                subscription
+            )),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Returns the current time in milliseconds since the Unix epoch.
+fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+/// Allows cancelling the request a [Client](Client) is waiting for from another thread,
+/// such as a long poll. Obtained through [cancellation_handle](Client::cancellation_handle).
+#[derive(Debug, Clone)]
+pub struct CancelHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelHandle {
+    /// Cancels the request in progress, or the next one if none is. The cancelled call
+    /// returns an error of kind [`Cancelled`](ErrorKind::Cancelled) right away, while the
+    /// request itself is left to complete in the background.
+    ///
+    /// A cancelled long poll therefore stays open on the server until it answers it. Calling
+    /// [connect](Client::connect) again in the meantime holds a second long poll for the same
+    /// client id, which the server usually answers with a `multiple-clients` advice. To move on
+    /// from a cancelled long poll, [disconnect](Client::disconnect), which makes the server
+    /// answer it, or wait for the timeout of the server before connecting again.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Adds the `ext` returned by the provider for the channel of the message to the one it
+/// already carries.
+fn add_provided_ext(message: &mut serde_json::Value, ext_provider: &ExtProvider) {
+    let fields = match message {
+        serde_json::Value::Object(fields) => fields,
+        _ => return,
+    };
+    let provided = match fields
+        .get("channel")
+        .and_then(serde_json::Value::as_str)
+        .and_then(ext_provider)
+    {
+        Some(provided) => provided,
+        None => return,
+    };
+
+    match (fields.get_mut("ext"), provided) {
+        (Some(serde_json::Value::Object(ext)), serde_json::Value::Object(provided)) => {
+            ext.extend(provided)
+        }
+        (None | Some(serde_json::Value::Null), provided) => {
+            fields.insert("ext".to_owned(), provided);
+        }
+        (Some(_), _) => log::warn!("Could not merge the provided ext with a non object ext"),
+    }
+}
+
+/// Builds the error returned when the server answers with a non-2xx status. Server errors
+/// are transport errors, so that the request is retried, while client errors such as a
+/// rejected authentication are protocol ones.
+fn status_error(status: StatusCode, body: &str, log_bodies: bool) -> Error {
+    let kind = if status.is_server_error() {
+        ErrorKind::Transport
+    } else {
+        ErrorKind::Protocol
+    };
+    let message = if log_bodies {
+        format!("Server responded with status {}: {}", status, excerpt(body))
+    } else {
+        format!("Server responded with status {}", status)
+    };
+
+    Error::new(kind, &message)
+        .with_body(body)
+        .with_status(status.as_u16())
+}
+
+/// Reads the cookies and the body of a response from the server.
+fn read_http_response(
+    resp: reqwest::blocking::Response,
+    log_bodies: bool,
+) -> Result<RawResponse, Error> {
+    let status = resp.status();
+    let cookies = resp
+        .cookies()
+        .map(|c| format!("{}={}", c.name(), c.value()))
+        .collect::<Vec<_>>();
+    let body = resp.text()?;
+
+    if !status.is_success() {
+        return Err(status_error(status, &body, log_bodies));
+    }
+    Ok(RawResponse { body, cookies })
+}
+
+/// Sends the request from another thread, waiting for it until it completes or is cancelled.
+fn send_cancellable(
+    req: RequestBuilder,
+    cancelled: &AtomicBool,
+    log_bodies: bool,
+) -> Result<RawResponse, Error> {
+    let cancelled_error = || Error::new(ErrorKind::Cancelled, "The request was cancelled");
+
+    if cancelled.swap(false, Ordering::SeqCst) {
+        return Err(cancelled_error());
+    }
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = tx.send(
+            req.send()
+                .map_err(Error::from)
+                .and_then(|resp| read_http_response(resp, log_bodies)),
+        );
+    });
+
+    loop {
+        match rx.recv_timeout(CANCEL_POLL_INTERVAL) {
+            Ok(resp) => return resp,
+            Err(RecvTimeoutError::Timeout) if cancelled.swap(false, Ordering::SeqCst) => {
+                return Err(cancelled_error())
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(Error::new(
+                    ErrorKind::Transport,
+                    "The request stopped without response",
+                ))
+            }
+        }
+    }
+}
+
+/// Exponential backoff applied before a request is retried.
+#[derive(Debug, Clone, Copy)]
+struct Backoff {
+    base: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    /// Returns the delay before the given attempt, starting at 1. It doubles with every
+    /// attempt, plus a random jitter of up to half of it, and never exceeds the maximum.
+    fn delay(&self, attempt: i8) -> Duration {
+        let exponent = attempt.saturating_sub(1).clamp(0, 31) as u32;
+        let delay = self.base.saturating_mul(1 << exponent).min(self.max);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.subsec_nanos())
+            .unwrap_or(0);
+        let jitter = delay.mul_f64(f64::from(nanos) / 2_000_000_000.0);
+
+        (delay + jitter).min(self.max)
+    }
+
+    /// Waits before the given attempt, for at most `remaining` if given.
+    fn wait(&self, attempt: i8, remaining: Option<Duration>) {
+        let delay = self.delay(attempt);
+        let delay = remaining.map_or(delay, |remaining| delay.min(remaining));
+
+        log::debug!("Backing off for {}ms", delay.as_millis());
+        thread::sleep(delay);
+    }
 }
 
 impl Client {
@@ -68,187 +577,1463 @@ impl Client {
     ///
     /// Will return an error if the http client cannot be initalized.
     pub fn new(base_url: &str, access_token: &str, timeout: Duration) -> Result<Client, Error> {
-        let url = Url::parse(base_url).map_err(|_| Error::new("Could not parse base url"))?;
-        let http_client = ReqwestClient::builder()
-            .cookie_store(true)
+        ClientBuilder::new()
+            .base_url(base_url)
+            .access_token(access_token)
             .timeout(timeout)
             .build()
-            .map_err(|_| Error::new("Could not initialize http client"))?;
-
-        log::info!("Successfully created cometd client");
-        Ok(Client {
-            http_client,
-            base_url: url,
-            access_token: access_token.to_owned(),
-            client_id: None,
-            cookies: vec![],
-            actual_retries: 0,
-            max_retries: 1,
-        })
+    }
+
+    /// Creates a new cometd client for a server which does not require authentication. No
+    /// `Authorization` header is sent, as with [`AuthScheme::None`](AuthScheme::None).
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the http client cannot be initalized.
+    pub fn new_unauthenticated(base_url: &str, timeout: Duration) -> Result<Client, Error> {
+        ClientBuilder::new()
+            .base_url(base_url)
+            .auth(AuthScheme::None)
+            .timeout(timeout)
+            .build()
+    }
+
+    /// Creates a new cometd client sending its requests through an existing http client,
+    /// for instance to share its configuration and connection pool. The timeout for
+    /// long-polling requests defaults to
+    /// [COMETD_DEFAULT_TIMEOUT](crate::config::COMETD_DEFAULT_TIMEOUT).
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the base url cannot be parsed.
+    pub fn with_http_client(
+        http_client: ReqwestClient,
+        base_url: &str,
+        access_token: &str,
+    ) -> Result<Client, Error> {
+        ClientBuilder::new()
+            .base_url(base_url)
+            .access_token(access_token)
+            .http_client(http_client)
+            .build()
+    }
+
+    /// Returns a [ClientBuilder](ClientBuilder) to configure the client before creating it.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
     }
 
     /// Sets the number of retries the client will attempt in case of an error or a retry advice is
-    /// returned by the cometd server.
+    /// returned by the cometd server. Retries are attempts made in addition to the first one, so
+    /// `0` disables them and a request is sent `retries + 1` times at most. This sets both
+    /// [set_transport_retries](Client::set_transport_retries) and
+    /// [set_advice_reconnects](Client::set_advice_reconnects).
     pub fn set_retries(mut self, retries: i8) -> Self {
-        self.max_retries = retries;
+        self.max_transport_retries = retries;
+        self.max_advice_reconnects = retries;
+        self
+    }
+
+    /// Sets the number of times a connect or handshake request is sent again when it could not
+    /// reach the server.
+    pub fn set_transport_retries(mut self, retries: i8) -> Self {
+        self.max_transport_retries = retries;
+        self
+    }
+
+    /// Sets an exponential backoff applied before every retry. The delay starts at `base`
+    /// and doubles with every attempt, with some jitter, up to `max`. By default, requests
+    /// are retried right away.
+    pub fn set_backoff(mut self, base: Duration, max: Duration) -> Self {
+        self.backoff = Some(Backoff { base, max });
+        self
+    }
+
+    /// Sets the time waited before reconnecting when the server did not advise any interval.
+    /// Defaults to `0`, that is reconnecting right away.
+    pub fn set_default_interval(mut self, interval: Duration) -> Self {
+        self.default_interval = interval;
+        self
+    }
+
+    /// Sets the time allowed on top of the timeout advised by the server for a connect
+    /// request, since the server holds it for the advised timeout before answering. Defaults
+    /// to [COMETD_MAX_NETWORK_DELAY](crate::config::COMETD_MAX_NETWORK_DELAY).
+    pub fn set_max_network_delay(mut self, delay: Duration) -> Self {
+        self.max_network_delay = delay;
+        self
+    }
+
+    /// Sets the number of times the client follows a reconnect advice from the server before
+    /// giving up. Following a handshake advice counts as a single retry, even though it sends
+    /// both a handshake and a connect.
+    pub fn set_advice_reconnects(mut self, reconnects: i8) -> Self {
+        self.max_advice_reconnects = reconnects;
+        self
+    }
+
+    /// Returns the number of times the client followed an advice from the server during the
+    /// last operation, such as a connect or a subscribe. The requests sent again because they
+    /// could not reach the server are counted by
+    /// [transport_retries_used](Client::transport_retries_used).
+    pub fn retries_used(&self) -> i8 {
+        self.advice_reconnects
+    }
+
+    /// Returns the number of times a request was sent again during the last operation because
+    /// it could not reach the server.
+    pub fn transport_retries_used(&self) -> i8 {
+        self.transport_retries
+    }
+
+    /// Resets the retry counters. They are reset at the start of every operation anyway, so
+    /// that an operation never inherits the retries of the previous one.
+    pub fn reset_retries(&mut self) {
+        self.advice_reconnects = 0;
+        self.transport_retries = 0;
+        self.retries_started = Instant::now();
+    }
+
+    /// Sets the maximum time an operation may spend following the advice of the server to
+    /// reconnect or retrying requests which did not reach it, counted from its start. The
+    /// waits before retrying are cut short to it, and once elapsed, the client stops retrying
+    /// even if the maximum number of retries is not reached. There is no limit by default.
+    pub fn set_max_reconnect_duration(mut self, duration: Duration) -> Self {
+        self.max_reconnect_duration = Some(duration);
+        self
+    }
+
+    /// Returns the client id negotiated with the server during the handshake, or `None`
+    /// if no handshake has been made yet.
+    pub fn client_id(&self) -> Option<&str> {
+        self.client_id.as_deref()
+    }
+
+    /// Returns the url of the cometd server the requests are sent to. It changes when the
+    /// client fails over to a host advised by the server.
+    pub fn base_url(&self) -> &str {
+        self.base_url.as_str()
+    }
+
+    /// Returns the state of the session with the server.
+    pub fn state(&self) -> ClientState {
+        self.state
+    }
+
+    /// Returns the most recent advice sent by the server, be it along with a successful
+    /// response or an errored one. Useful to tune the timing of a custom polling loop.
+    pub fn last_advice(&self) -> Option<&Advice> {
+        self.advice.as_ref()
+    }
+
+    /// Returns the timeout currently applied to connect requests. It is the one provided to
+    /// the client until the server advises how long it holds a connect request, which is then
+    /// used with the [max network delay](Client::set_max_network_delay) added. The other
+    /// requests always use the timeout provided to the client.
+    /// Returns [`Duration::MAX`](Duration::MAX) if the client was built without timeout, since
+    /// it then waits for the server as long as needed.
+    pub fn current_timeout(&self) -> Duration {
+        self.connect_timeout().unwrap_or(Duration::MAX)
+    }
+
+    fn connect_timeout(&self) -> Option<Duration> {
+        let advised = self.advice.as_ref().and_then(|advice| advice.timeout);
+
+        match (self.timeout, advised) {
+            (Some(_), Some(advised)) => {
+                Some(Duration::from_millis(u64::from(advised)) + self.max_network_delay)
+            }
+            (timeout, _) => timeout,
+        }
+    }
+
+    /// Returns the timeout of a request, which depends on whether it is a connect one.
+    fn request_timeout(&self, channel: Option<&str>) -> Option<Duration> {
+        if let Some(timeout) = self.timeout_override {
+            return Some(timeout);
+        }
+        if channel == Some(channel::CONNECT) {
+            self.connect_timeout()
+        } else {
+            self.timeout
+        }
+    }
+
+    /// Sets the authentication scheme used to build the authentication header of
+    /// every request. Defaults to [`AuthScheme::OAuth`](AuthScheme::OAuth).
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the scheme sends credentials while the url of the server does
+    /// not use https and it is [required](ClientBuilder::require_https).
+    pub fn set_auth(mut self, auth: AuthScheme) -> Result<Self, Error> {
+        self.auth = auth;
+        self.check_https(&self.base_url)?;
+        self.sync_session();
+        Ok(self)
+    }
+
+    /// Checks that no credentials would be sent in clear to a url while https is
+    /// [required](ClientBuilder::require_https). Custom headers count as credentials.
+    fn check_https(&self, url: &Url) -> Result<(), Error> {
+        let credentials = self.auth.has_credentials(&self.access_token) || !self.headers.is_empty();
+
+        if self.require_https && credentials && url.scheme() != "https" {
+            let message = format!("{} must use https to send credentials", url);
+
+            return Err(Error::new(ErrorKind::Parse, &message));
+        }
+        Ok(())
+    }
+
+    /// Sets the transport used to exchange messages with the server. Defaults to
+    /// [`Transport::LongPolling`](Transport::LongPolling). Without the `websocket` feature,
+    /// every request fails with [`Transport::WebSocket`](Transport::WebSocket), as it does if
+    /// the client was built with TLS or proxy options the WebSocket does not support, see
+    /// [ClientBuilder::transport](ClientBuilder::transport).
+    pub fn set_transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Posts the messages of a channel to a path under the base url instead of the base url
+    /// itself, for servers exposing separate endpoints such as `/cometd/handshake`. It only
+    /// applies to the long-polling transport.
+    pub fn set_endpoint_path(mut self, channel: &str, path: &str) -> Self {
+        self.endpoints.insert(channel.to_owned(), path.to_owned());
+        self
+    }
+
+    /// Sets the connection type used in the connect messages and advertised during the
+    /// handshake, for servers expecting another one than the transport's, such as
+    /// `callback-polling`.
+    pub fn set_connection_type(mut self, connection_type: &str) -> Self {
+        self.connection_type = Some(connection_type.to_owned());
+        self
+    }
+
+    /// Sets the connection types advertised during the handshake, in order of preference, so
+    /// that the server picks among them. The connect messages then use the first one the server
+    /// supports. Defaults to the single connection type used in the connect messages.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the [transport](Client::set_transport) cannot carry one of the
+    /// types, so it must be set first. The handshake fails the same way if the transport is
+    /// changed afterwards.
+    pub fn set_supported_connection_types(
+        mut self,
+        connection_types: Vec<String>,
+    ) -> Result<Self, Error> {
+        for connection_type in connection_types.iter() {
+            self.transport.check_carries(connection_type)?;
+        }
+        self.supported_connection_types = connection_types;
+        Ok(self)
+    }
+
+    /// Returns the connection type negotiated during the last handshake, or the configured
+    /// one before any handshake.
+    fn connection_type(&self) -> String {
+        match self.negotiated_connection_type {
+            Some(ref connection_type) => connection_type.clone(),
+            None => self.configured_connection_type(),
+        }
+    }
+
+    fn configured_connection_type(&self) -> String {
+        match self.connection_type {
+            Some(ref connection_type) => connection_type.clone(),
+            None => self.transport.connection_type().to_owned(),
+        }
+    }
+
+    fn advertised_connection_types(&self) -> Vec<String> {
+        if self.supported_connection_types.is_empty() {
+            vec![self.configured_connection_type()]
+        } else {
+            self.supported_connection_types.clone()
+        }
+    }
+
+    /// Returns the connection types the server reported supporting during the last handshake,
+    /// or an empty slice if no handshake has been made yet.
+    pub fn server_supported_types(&self) -> &[String] {
+        &self.server_supported_types
+    }
+
+    /// Returns the id of the last message sent to the server. Ids are incremented for
+    /// every message so responses can be correlated with the requests.
+    pub fn message_id(&self) -> u64 {
+        self.message_id
+    }
+
+    fn next_message_id(&mut self) -> String {
+        self.message_id += 1;
+        self.message_id.to_string()
+    }
+
+    /// Sets the `ext` field sent along with the handshake message. Some servers expect
+    /// authentication data in there rather than in an HTTP header.
+    pub fn set_handshake_ext(mut self, ext: serde_json::Value) -> Self {
+        self.handshake_ext = Some(ext);
+        self
+    }
+
+    /// Sets whether the client handshakes again when the server answers with a `402` unknown
+    /// client error without any advice, as if it had advised to. Defaults to `false`.
+    pub fn set_auto_rehandshake_on_unknown_client(mut self, rehandshake: bool) -> Self {
+        self.rehandshake_on_unknown_client = rehandshake;
+        self
+    }
+
+    /// Sets whether the client fails over to the hosts advised by the server when it cannot
+    /// reach the current one anymore. The advised hosts are tried in order and the first one
+    /// answering becomes the url of the server for the subsequent requests. Defaults to `false`.
+    pub fn set_follow_advice_hosts(mut self, follow_advice_hosts: bool) -> Self {
+        self.follow_advice_hosts = follow_advice_hosts;
+        self
+    }
+
+    /// Returns the cookies of the session, as last received from the server.
+    /// Each cookie is formatted as `name=value`.
+    /// They can be saved and later given to [set_cookies](Client::set_cookies) to resume the
+    /// session with another client.
+    pub fn cookies(&self) -> &[String] {
+        &self.cookies
+    }
+
+    /// Sets the cookies sent along with every request. It can be called before
+    /// [init](Client::init) to restore a previously saved session.
+    pub fn set_cookies(mut self, cookies: Vec<String>) -> Self {
+        self.cookies = cookies;
+        self.sync_session();
+        self
+    }
+
+    /// Adds a header sent along with every request, such as a tenant id or a CSRF token
+    /// required by a gateway. It takes precedence over the authentication header if they
+    /// share the same name.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the name or the value is not a valid header name or value, or
+    /// if the url of the server does not use https while it is
+    /// [required](ClientBuilder::require_https).
+    pub fn set_header(mut self, name: &str, value: &str) -> Result<Self, Error> {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|err| Error::with_source(ErrorKind::Parse, "Invalid header name", err))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|err| Error::with_source(ErrorKind::Parse, "Invalid header value", err))?;
+
+        self.headers.insert(name, value);
+        self.check_https(&self.base_url)?;
+        self.sync_session();
+        Ok(self)
+    }
+
+    /// Sets the replay id from which the server should send the events of a channel, as
+    /// supported by Salesforce. The replay ids are sent in the `ext` of the handshake and
+    /// subscribe messages, and updated with the ids of the events received on those channels
+    /// so resubscribing resumes after the last received event.
+    pub fn set_replay(mut self, channel: &str, replay_id: i64) -> Self {
+        self.replay.insert(channel.to_owned(), replay_id);
+        self
+    }
+
+    /// Returns the replay id of a channel, which is the one of the last event received on
+    /// it or the one given to [set_replay](Client::set_replay).
+    pub fn replay_id(&self, channel: &str) -> Option<i64> {
+        self.replay.get(channel).copied()
+    }
+
+    /// Returns the `ext` of a message with the replay ids added, if any were set.
+    fn with_replay_ext(&self, ext: Option<&serde_json::Value>) -> Option<serde_json::Value> {
+        if self.replay.is_empty() {
+            return ext.cloned();
+        }
+        let mut ext = match ext {
+            Some(serde_json::Value::Object(ext)) => ext.clone(),
+            Some(ext) => {
+                log::warn!("Could not add the replay ids to a non object ext");
+                return Some(ext.clone());
+            }
+            None => serde_json::Map::new(),
+        };
+
+        ext.insert("replay".to_owned(), serde_json::json!(self.replay));
+        Some(serde_json::Value::Object(ext))
+    }
+
+    /// Sets the `version` field sent along with the handshake message, for servers pinned to
+    /// a specific version of the Bayeux protocol. Defaults to
+    /// [`COMETD_VERSION`](crate::config::COMETD_VERSION).
+    pub fn set_version(mut self, version: &str) -> Self {
+        self.version = version.to_owned();
+        self
+    }
+
+    /// Enables the timesync extension. The client then sends its time along with every connect
+    /// message and estimates the offset of its clock and the network lag from the replies of
+    /// the server. Disabled by default.
+    pub fn enable_timesync(mut self, enabled: bool) -> Self {
+        self.timesync = enabled;
+        self
+    }
+
+    /// Returns the offset in milliseconds between the clock of the server and the one of the
+    /// client, as estimated by the timesync extension, or `None` if it has not been yet.
+    pub fn clock_offset(&self) -> Option<i64> {
+        self.clock_offset
+    }
+
+    /// Returns the one-way network lag in milliseconds, as estimated by the timesync
+    /// extension, or `None` if it has not been yet.
+    pub fn network_lag(&self) -> Option<u32> {
+        self.network_lag
+    }
+
+    /// Enables the acknowledgement extension, for reliable and ordered delivery on servers
+    /// supporting it. The client then sends back, along with every connect message, the id of
+    /// the last batch of messages the server acknowledged, so that the server sends again the
+    /// messages lost on a reconnect. Disabled by default.
+    pub fn enable_ack_extension(mut self, enabled: bool) -> Self {
+        self.ack_extension = enabled;
+        self
+    }
+
+    /// Returns a handle allowing to cancel the request the client is waiting for from another
+    /// thread, such as a [connect](Client::connect) hanging until the timeout. Once a handle
+    /// exists, the requests are sent from a background thread so they can be given up on. Only
+    /// the long-polling transport can be cancelled.
+    pub fn cancellation_handle(&mut self) -> CancelHandle {
+        let cancelled = self
+            .cancelled
+            .get_or_insert_with(|| Arc::new(AtomicBool::new(false)));
+
+        CancelHandle {
+            cancelled: cancelled.clone(),
+        }
+    }
+
+    /// Returns a handle on the session of the client, allowing to publish from other threads
+    /// while the client keeps long-polling the server. See [Session](Session).
+    pub fn session(&mut self) -> Session {
+        let base_url = self.base_url.clone();
+        let shared = self
+            .session
+            .get_or_insert_with(|| {
+                Arc::new(SharedSession::new(SessionState {
+                    client_id: None,
+                    base_url,
+                    headers: HeaderMap::new(),
+                }))
+            })
+            .clone();
+
+        self.sync_session();
+        Session::new(
+            self.http_client.clone(),
+            self.timeout,
+            self.log_bodies,
+            shared,
+        )
+    }
+
+    /// Shares the current client id, url and headers with the sessions, if any. Only called
+    /// when one of them changes, since it builds a request to get the headers.
+    fn sync_session(&self) {
+        let shared = match self.session {
+            Some(ref shared) => shared,
+            None => return,
+        };
+        let headers = self
+            .with_headers(self.http_client.post(self.base_url.clone()))
+            .build()
+            .map(|req| req.headers().clone())
+            .unwrap_or_default();
+
+        if let Ok(mut state) = shared.state.write() {
+            state.client_id = self.client_id.clone();
+            state.base_url = self.base_url.clone();
+            state.headers = headers;
+        }
+    }
+
+    /// Sets the `minimumVersion` field sent along with the handshake message. It is not
+    /// sent by default.
+    pub fn set_minimum_version(mut self, minimum_version: &str) -> Self {
+        self.minimum_version = Some(minimum_version.to_owned());
+        self
+    }
+
+    /// Sets whether the messages the server reports as failed are set aside rather than failing
+    /// the whole request, so that the successful messages of a batch are still returned. The
+    /// errors set aside can be retrieved with [take_partial_errors](Client::take_partial_errors).
+    /// Failures the server advised to recover from are still followed, and a rejected
+    /// subscription still fails [subscribe](Client::subscribe). Defaults to `false`.
+    pub fn set_partial_results(mut self, partial_results: bool) -> Self {
+        self.partial_results = partial_results;
+        self
+    }
+
+    /// Returns the errors set aside since the last call when partial results are enabled,
+    /// see [set_partial_results](Client::set_partial_results).
+    pub fn take_partial_errors(&mut self) -> Vec<Error> {
+        std::mem::take(&mut self.partial_errors)
+    }
+
+    /// Sets whether a connect request that times out is considered as a long-poll that ended
+    /// without any message, in which case [connect](Client::connect) returns an empty `Vec`
+    /// instead of an error and the request is not retried. Defaults to `false`.
+    pub fn set_treat_timeout_as_empty(mut self, treat_timeout_as_empty: bool) -> Self {
+        self.treat_timeout_as_empty = treat_timeout_as_empty;
+        self
+    }
+
+    /// Sets the maximum number of messages accepted in a single response from the server.
+    /// A response carrying more messages is rejected with an error, protecting against a
+    /// misbehaving server flooding the client. There is no limit by default.
+    pub fn set_max_messages_per_poll(mut self, max_messages: usize) -> Self {
+        self.max_messages_per_poll = Some(max_messages);
+        self
+    }
+
+    /// Sets how many of the last deliveries received are kept in memory, to be retrieved
+    /// through [recent_messages](Client::recent_messages). None are kept by default.
+    pub fn set_buffer_size(mut self, size: usize) -> Self {
+        self.buffer_size = size;
+        self.recent_messages.truncate(size);
+        self
+    }
+
+    /// Returns the last deliveries received, from the oldest to the most recent, up to the
+    /// [buffer size](Client::set_buffer_size).
+    pub fn recent_messages(&self) -> Vec<Response> {
+        self.recent_messages.iter().cloned().collect()
+    }
+
+    /// Keeps the deliveries among the responses in the buffer of recent messages, dropping
+    /// the oldest ones beyond its size.
+    fn buffer_messages(&mut self, resps: &[Response]) {
+        if self.buffer_size == 0 {
+            return;
+        }
+        for resp in resps {
+            if let Response::Delivery(_) = resp {
+                if self.recent_messages.len() == self.buffer_size {
+                    self.recent_messages.pop_front();
+                }
+                self.recent_messages.push_back(resp.clone());
+            }
+        }
+    }
+
+    /// Sets the function turning the messages sent to the server into JSON, in place of
+    /// `serde_json::to_string`. Useful for servers expecting a specific formatting, or to
+    /// pretty-print the messages while debugging.
+    pub fn set_serializer(mut self, serializer: Serializer) -> Self {
+        self.serializer = Some(serializer);
+        self
+    }
+
+    /// Sets a function called with the channel of every message sent to the server, be it a
+    /// meta one or a publish, and returning the `ext` to add to it. The fields it returns are
+    /// added to the `ext` the message already carries, replacing the ones with the same name.
+    /// Since it is called again when a message is retried, it may return a different `ext`
+    /// every time, such as a nonce. The messages published through a [Session](Session) are
+    /// left untouched.
+    pub fn set_ext_provider(mut self, ext_provider: ExtProvider) -> Self {
+        self.ext_provider = Some(ext_provider);
         self
     }
 
-    fn send_request(&self, body: &impl Serialize) -> Result<ReqwestReponse, Error> {
+    /// Sets whether the requests are kept instead of being sent to the server, which allows
+    /// to check the messages built by the client without any server. Every message is then
+    /// answered as successful, handshakes with the `dry-run` client id. The requests can be
+    /// retrieved with [take_dry_run_requests](Client::take_dry_run_requests). Defaults to
+    /// `false`.
+    pub fn set_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Returns the JSON bodies of the requests kept since the last call in dry run mode, see
+    /// [set_dry_run](Client::set_dry_run).
+    pub fn take_dry_run_requests(&mut self) -> Vec<String> {
+        self.dry_run_requests
+            .get_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Sets where the counters of the client activity are reported: requests sent, retries,
+    /// handshakes and errors by kind. See the [metrics](crate::metrics) module for their names.
+    /// By default, they are discarded.
+    pub fn set_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Registers a callback invoked every time the client reconnects or handshakes again
+    /// following an advice from the server. Useful to monitor the health of the session.
+    pub fn on_reconnect(mut self, callback: ReconnectCallback) -> Self {
+        self.on_reconnect = Some(callback);
+        self
+    }
+
+    /// Registers a callback invoked with the response of every successful handshake, be it
+    /// the first one or one made following an advice from the server.
+    pub fn on_handshake(mut self, callback: HandshakeCallback) -> Self {
+        self.on_handshake = Some(callback);
+        self
+    }
+
+    /// Sets how long [listen](Client::listen) waits for a message to be delivered before
+    /// invoking the [on_idle](Client::on_idle) callback. Unlike the request timeout, it spans
+    /// several connect requests, so it detects a feed which has gone quiet while the
+    /// connection is kept alive. Disabled by default.
+    pub fn set_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    /// Registers a callback invoked when no message has been delivered for longer than the
+    /// idle timeout while listening. It is invoked again after every new idle timeout without
+    /// any message.
+    pub fn on_idle(mut self, callback: IdleCallback) -> Self {
+        self.on_idle = Some(callback);
+        self
+    }
+
+    /// Sets whether the client disconnects from the server when it is dropped. Defaults to
+    /// `true`. Since the disconnect request blocks until the server answers, it may be
+    /// disabled to drop the client without waiting.
+    pub fn set_auto_disconnect(mut self, auto_disconnect: bool) -> Self {
+        self.auto_disconnect = auto_disconnect;
+        self
+    }
+
+    /// Sets whether the bodies of the requests and responses are logged. When disabled, only
+    /// the channels and the sizes of the messages are, so that sensitive data never ends up in
+    /// the logs, nor in the errors raised for unparsable responses. Defaults to `true`.
+    pub fn set_log_bodies(mut self, log_bodies: bool) -> Self {
+        self.log_bodies = log_bodies;
+        self
+    }
+
+    /// Sets whether the responses carrying fields the client does not know of are rejected
+    /// with a [`Parse`](ErrorKind::Parse) error rather than having these fields ignored.
+    /// Useful to catch a server drifting from the protocol. Defaults to `false`.
+    pub fn set_strict_parsing(mut self, strict_parsing: bool) -> Self {
+        self.strict_parsing = strict_parsing;
+        self
+    }
+
+    /// Adds the authentication, cookie and custom headers to a request.
+    fn with_headers(&self, mut req: RequestBuilder) -> RequestBuilder {
+        req = match self.auth {
+            AuthScheme::OAuth => req.header(
+                reqwest::header::AUTHORIZATION,
+                format!("OAuth {}", self.access_token),
+            ),
+            AuthScheme::Bearer => req.bearer_auth(&self.access_token),
+            AuthScheme::Basic { ref user, ref pass } => req.basic_auth(user, Some(pass)),
+            AuthScheme::Custom {
+                ref header_name,
+                ref value,
+            } => req.header(header_name.as_str(), value.as_str()),
+            AuthScheme::None => req,
+        };
+        if !self.cookies.is_empty() {
+            req = req.header(reqwest::header::COOKIE, self.cookies.join("; "));
+        }
+        req.headers(self.headers.clone())
+    }
+
+    /// Sends a request to the server. The channel of the message is given by the caller, or
+    /// `None` for a batch, so that the body is only serialized once.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug", skip_all, fields(client_id = self.client_id.as_deref())
+        )
+    )]
+    fn send_request(
+        &self,
+        channel: Option<&str>,
+        body: &impl Serialize,
+    ) -> Result<RawResponse, Error> {
+        let text = self.serialize(body)?;
+
+        if self.log_bodies {
+            log::debug!(
+                "Sending request to cometd with the following body: {}",
+                text
+            );
+        } else {
+            log::debug!(
+                "Sending request to cometd on {} ({} bytes)",
+                channel.unwrap_or("several channels"),
+                text.len()
+            );
+        }
+        self.metrics.incr(metrics::REQUESTS);
+        if self.dry_run {
+            return self.record_request(body, text);
+        }
+        let resp = match self.transport {
+            Transport::LongPolling => self.send_http_request(channel, text),
+            #[cfg(feature = "websocket")]
+            Transport::WebSocket => self.send_websocket_message(text),
+            #[cfg(not(feature = "websocket"))]
+            Transport::WebSocket => Err(Error::new(
+                ErrorKind::Transport,
+                "The websocket transport requires the websocket feature",
+            )),
+        };
+
+        if let Err(ref err) = resp {
+            self.metrics.incr(metrics::error_counter(err.kind()));
+        }
+        resp
+    }
+
+    /// Turns a message into the JSON sent to the server, with the custom serializer if one
+    /// is set, after adding the `ext` of the provider if one is set.
+    fn serialize(&self, body: &impl Serialize) -> Result<String, Error> {
+        let ext_provider = match (&self.ext_provider, &self.serializer) {
+            (Some(ext_provider), _) => ext_provider,
+            (None, Some(serializer)) => return serializer(&serde_json::to_value(body)?),
+            (None, None) => return Ok(serde_json::to_string(body)?),
+        };
+        let mut body = serde_json::to_value(body)?;
+
+        match body {
+            serde_json::Value::Array(ref mut messages) => {
+                for message in messages {
+                    add_provided_ext(message, ext_provider);
+                }
+            }
+            ref mut message => add_provided_ext(message, ext_provider),
+        }
+        match self.serializer {
+            Some(ref serializer) => serializer(&body),
+            None => Ok(serde_json::to_string(&body)?),
+        }
+    }
+
+    /// Keeps the request instead of sending it in dry run mode, and answers it as a server
+    /// accepting every message would.
+    fn record_request(&self, body: &impl Serialize, text: String) -> Result<RawResponse, Error> {
+        let messages = match serde_json::to_value(body)? {
+            serde_json::Value::Array(messages) => messages,
+            message => vec![message],
+        };
+        let resps = messages
+            .iter()
+            .map(|message| {
+                let mut resp = serde_json::json!({
+                    "channel": message["channel"],
+                    "successful": true,
+                    "id": message["id"],
+                });
+                if message["channel"] == channel::HANDSHAKE {
+                    resp["version"] = self.version.as_str().into();
+                    resp["clientId"] = DRY_RUN_CLIENT_ID.into();
+                    resp["supportedConnectionTypes"] = message["supportedConnectionTypes"].clone();
+                }
+                resp
+            })
+            .collect::<Vec<_>>();
+
+        if let Ok(mut requests) = self.dry_run_requests.lock() {
+            requests.push(text);
+        }
+        Ok(RawResponse {
+            body: serde_json::Value::Array(resps).to_string(),
+            cookies: vec![],
+        })
+    }
+
+    /// Returns the url a message is posted to, depending on its channel.
+    fn endpoint_url(&self, channel: Option<&str>) -> Url {
+        let path = channel.and_then(|channel| self.endpoints.get(channel));
+
+        match path {
+            Some(path) => {
+                let mut url = self.base_url.clone();
+                let base_path = url.path().trim_end_matches('/').to_owned();
+
+                url.set_path(&format!("{}/{}", base_path, path.trim_start_matches('/')));
+                url
+            }
+            None => self.base_url.clone(),
+        }
+    }
+
+    fn send_http_request(&self, channel: Option<&str>, text: String) -> Result<RawResponse, Error> {
         let mut req = self
             .http_client
-            .post(self.base_url.clone())
-            .header("Authorization", &format!("OAuth {}", self.access_token))
-            .json(body);
+            .post(self.endpoint_url(channel))
+            .header(CONTENT_TYPE, "application/json")
+            .header(ACCEPT, "application/json")
+            .body(text);
 
-        for ref cookie in self.cookies.iter() {
-            req = req.header(reqwest::header::SET_COOKIE, cookie.clone());
+        if let Some(timeout) = self.request_timeout(channel) {
+            req = req.timeout(timeout);
         }
+        let req = self.with_headers(req);
+
+        match self.cancelled {
+            Some(ref cancelled) => send_cancellable(req, cancelled, self.log_bodies),
+            None => read_http_response(req.send()?, self.log_bodies),
+        }
+    }
+
+    /// Sends the message through the websocket, opening it first if needed.
+    #[cfg(feature = "websocket")]
+    fn send_websocket_message(&self, body: String) -> Result<RawResponse, Error> {
+        let mut websocket = self
+            .websocket
+            .lock()
+            .map_err(|_| Error::new(ErrorKind::Transport, "Websocket is poisoned"))?;
+
+        if let Some(option) = self.websocket_unsupported_option {
+            let message = format!(
+                "The websocket transport does not support the {} option",
+                option
+            );
+
+            return Err(Error::new(ErrorKind::Transport, &message));
+        }
+        if websocket.is_none() {
+            let req = self
+                .with_headers(self.http_client.get(self.base_url.clone()))
+                .build()?;
+
+            *websocket = Some(websocket::connect_websocket(
+                &self.base_url,
+                req.headers(),
+                self.timeout,
+            )?);
+        }
+
+        let mut unrelated = self
+            .websocket_unrelated
+            .lock()
+            .map_err(|_| Error::new(ErrorKind::Transport, "Websocket is poisoned"))?;
+        match websocket.as_mut() {
+            Some(socket) => match websocket::exchange(socket, body, &mut unrelated) {
+                Ok(body) => Ok(RawResponse {
+                    body,
+                    cookies: vec![],
+                }),
+                Err(err) => {
+                    // The connection is reopened on the next message.
+                    *websocket = None;
+                    Err(err)
+                }
+            },
+            None => Err(Error::new(ErrorKind::Transport, "Websocket is not open")),
+        }
+    }
+
+    /// Keeps the messages the server pushed through the websocket while the client was waiting
+    /// for a reply, so that they are returned by the next poll. The replies to the requests
+    /// which were given up on, such as a connect which timed out, are dropped.
+    #[cfg(feature = "websocket")]
+    fn keep_unrelated_messages(&mut self) {
+        let frames = match self.websocket_unrelated.get_mut() {
+            Ok(frames) => std::mem::take(frames),
+            Err(_) => return,
+        };
+
+        for frame in frames {
+            match parse_body(&frame, self.log_bodies) {
+                Ok(ParsedBody::Successful(resps)) => {
+                    for resp in resps {
+                        match resp {
+                            Response::Delivery(ref delivery) => {
+                                self.update_replay_id(delivery);
+                                self.pending_messages.push_back(resp);
+                            }
+                            Response::Publish(_) => self.pending_messages.push_back(resp),
+                            resp => log::debug!("Dropping stale reply on {}", resp.channel()),
+                        }
+                    }
+                }
+                Ok(ParsedBody::Errored(resps)) => {
+                    for resp in resps {
+                        log::debug!("Dropping stale error reply on {}", resp.channel);
+                    }
+                }
+                Err(err) => log::warn!("Dropping unparsable websocket message: {}", err),
+            }
+        }
+    }
+
+    /// Sends the request, sending it again if it could not reach the server until the
+    /// maximum number of transport retries is reached.
+    fn send_request_with_retries(
+        &mut self,
+        channel: Option<&str>,
+        body: &impl Serialize,
+    ) -> Result<RawResponse, Error> {
+        loop {
+            match self.send_request(channel, body) {
+                Err(ref err)
+                    if err.kind() == ErrorKind::Transport
+                        && !(self.treat_timeout_as_empty && err.is_timeout())
+                        && self.transport_retries < self.max_transport_retries
+                        && self.remaining_reconnect_duration() != Some(Duration::ZERO) =>
+                {
+                    self.transport_retries += 1;
+                    log::debug!("Transport retry n°{}", self.transport_retries);
+                    self.metrics.incr(metrics::TRANSPORT_RETRIES);
+                    if let Some(backoff) = self.backoff {
+                        backoff.wait(self.transport_retries, self.remaining_reconnect_duration());
+                    }
+                }
+                Err(err)
+                    if err.kind() == ErrorKind::Transport
+                        && !(self.treat_timeout_as_empty && err.is_timeout())
+                        && self.follow_advice_hosts =>
+                {
+                    return self.fail_over(channel, body).ok_or(err)
+                }
+                resp => return resp,
+            }
+        }
+    }
+
+    /// Sends the request to the hosts advised by the server, in order, until one of them
+    /// answers. The first one answering replaces the url of the server.
+    fn fail_over(&mut self, channel: Option<&str>, body: &impl Serialize) -> Option<RawResponse> {
+        let hosts = self
+            .advice
+            .as_ref()
+            .and_then(|advice| advice.hosts.clone())
+            .unwrap_or_default();
+
+        for host in hosts {
+            let url = match Url::parse(&host) {
+                Ok(url) if url != self.base_url => url,
+                Ok(_) => continue,
+                Err(err) => {
+                    log::warn!("Ignoring invalid advised host {}: {}", host, err);
+                    continue;
+                }
+            };
+            if let Err(err) = self.check_https(&url) {
+                log::warn!("Ignoring advised host {}: {}", host, err);
+                continue;
+            }
+            let primary = std::mem::replace(&mut self.base_url, url);
+
+            match self.send_request(channel, body) {
+                Ok(resp) => {
+                    log::info!("Failed over from {} to {}", primary, self.base_url);
+                    self.sync_session();
+                    return Some(resp);
+                }
+                Err(err) => {
+                    log::debug!("Could not reach advised host {}: {}", host, err);
+                    self.base_url = primary;
+                }
+            }
+        }
+        None
+    }
+
+    fn send_connect(&mut self) -> Result<RawResponse, Error> {
+        let connection_type = self.connection_type();
+        match self.client_id.clone() {
+            Some(client_id) => {
+                let id = self.next_message_id();
+                self.send_request_with_retries(
+                    Some(channel::CONNECT),
+                    &ConnectPayload {
+                        channel: channel::CONNECT,
+                        client_id: &client_id,
+                        connection_type: &connection_type,
+                        id,
+                        ext: self.connect_ext(),
+                    },
+                )
+            }
+            None => Err(Error::new(
+                ErrorKind::NotHandshaked,
+                "No client id set for connect",
+            )),
+        }
+    }
+
+    fn send_handshake(&mut self) -> Result<RawResponse, Error> {
+        for connection_type in self.supported_connection_types.iter() {
+            self.transport.check_carries(connection_type)?;
+        }
+        let id = self.next_message_id();
+        let mut ext = self.with_replay_ext(self.handshake_ext.as_ref());
+        if self.ack_extension {
+            match ext.get_or_insert_with(|| serde_json::json!({})) {
+                serde_json::Value::Object(ext) => {
+                    ext.insert("ack".to_owned(), true.into());
+                }
+                _ => log::warn!("Could not add the ack extension to a non object ext"),
+            }
+        }
+        let version = self.version.clone();
+        let minimum_version = self.minimum_version.clone();
+        let connection_types = self.advertised_connection_types();
+        self.send_request_with_retries(
+            Some(channel::HANDSHAKE),
+            &HandshakePayload {
+                channel: channel::HANDSHAKE,
+                version: &version,
+                minimum_version: minimum_version.as_deref(),
+                supported_connection_types: connection_types.iter().map(String::as_str).collect(),
+                id,
+                ext: ext.as_ref(),
+            },
+        )
+    }
+
+    /// Sends a request which is sent again rather than a connect when the server advises to
+    /// retry, and handles its response.
+    fn send_retryable(&mut self, body: &impl Serialize) -> Result<Vec<Response>, Error> {
+        let body = serde_json::to_value(body)?;
+        let resp = self.send_request(body_channel(&body), &body)?;
+        let previous = self.retried_request.replace(body);
+        let resps = self.handle_response(resp, false);
+
+        self.retried_request = previous;
+        resps
+    }
+
+    /// Sends again the request the server advised to retry, see [prepare_resend].
+    fn resend(&mut self, mut body: serde_json::Value) -> Result<RawResponse, Error> {
+        let client_id = self.client_id.clone();
+
+        prepare_resend(&mut body, client_id.as_deref(), || self.next_message_id());
+        self.send_request_with_retries(body_channel(&body), &body)
+    }
+
+    /// Returns the ext sent along with a connect message for the enabled extensions, if any.
+    fn connect_ext(&self) -> Option<serde_json::Value> {
+        let mut ext = serde_json::Map::new();
+
+        if self.timesync {
+            let timesync = serde_json::json!({
+                "tc": now_millis(),
+                "l": self.network_lag.unwrap_or(0),
+                "o": self.clock_offset.unwrap_or(0),
+            });
+            ext.insert("timesync".to_owned(), timesync);
+        }
+        if self.ack_extension {
+            // The server expects -1 until it sent a first ack id.
+            ext.insert("ack".to_owned(), self.ack_id.unwrap_or(-1).into());
+        }
+        if ext.is_empty() {
+            None
+        } else {
+            Some(serde_json::Value::Object(ext))
+        }
+    }
+
+    /// Estimates the network lag and clock offset from the timesync reply of the server,
+    /// which carries the time the client sent the message at (`tc`), the time the server
+    /// received it at (`ts`) and how long the server took to process it (`p`).
+    fn update_timesync(&mut self, ext: &serde_json::Value) {
+        let timesync = &ext["timesync"];
+        let (tc, ts) = match (timesync["tc"].as_i64(), timesync["ts"].as_i64()) {
+            (Some(tc), Some(ts)) => (tc, ts),
+            _ => return,
+        };
+        let processing = timesync["p"].as_i64().unwrap_or(0);
+        let lag = ((now_millis() - tc - processing) / 2).max(0);
+        let offset = ts - tc - lag;
 
         log::debug!(
-            "Sending request to cometd with the following body: {:?}",
-            serde_json::to_string(body)
+            "Estimated a clock offset of {}ms and a network lag of {}ms",
+            offset,
+            lag
+        );
+        self.network_lag = u32::try_from(lag).ok();
+        self.clock_offset = Some(offset);
+    }
+
+    fn retry(&mut self) -> Result<Vec<Response>, Error> {
+        let resp = self.send_connect()?;
+
+        self.handle_response(resp, false)
+    }
+
+    fn retry_handshake(&mut self) -> Result<Vec<Response>, Error> {
+        let resp = self.send_handshake()?;
+
+        self.handle_response(resp, true)
+    }
+
+    /// Waits before reconnecting for the interval advised by the server, or for the default
+    /// interval if it did not advise any. An advised interval of `0` means reconnecting right
+    /// away, whatever the default interval. The wait lasts at most `remaining` if given.
+    fn wait_for_interval(&self, advice: Option<&Advice>, remaining: Option<Duration>) {
+        let interval = match advice.and_then(|advice| advice.interval) {
+            Some(interval) => Duration::from_millis(u64::from(interval)),
+            None => self.default_interval,
+        };
+        let interval = remaining.map_or(interval, |remaining| interval.min(remaining));
+
+        if !interval.is_zero() {
+            log::debug!("Waiting {}ms before reconnecting", interval.as_millis());
+            thread::sleep(interval);
+        }
+    }
+
+    /// Returns the time left before the [maximum reconnect
+    /// duration](Client::set_max_reconnect_duration) of the operation is reached, if any. The
+    /// waits before retrying are cut short to it.
+    fn remaining_reconnect_duration(&self) -> Option<Duration> {
+        self.max_reconnect_duration
+            .map(|max| max.saturating_sub(self.retries_started.elapsed()))
+    }
+
+    fn check_reconnect_duration(&self, error: Option<&str>) -> Result<(), Error> {
+        match self.max_reconnect_duration {
+            Some(max) if self.remaining_reconnect_duration() == Some(Duration::ZERO) => {
+                Err(Error::new(
+                    ErrorKind::MaxRetriesReached,
+                    error.unwrap_or(&format!(
+                        "Max reconnect duration of {}ms reached",
+                        max.as_millis()
+                    )),
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Keeps the advice for the timing of the next requests.
+    fn store_advice(&mut self, advice: &Advice) {
+        self.advice = Some(advice.clone());
+    }
+
+    /// Subscribes again to the channels the client was subscribed to, since a new handshake
+    /// starts a new session on the server.
+    fn resubscribe(&mut self) -> Result<(), Error> {
+        let channels = self.subscriptions.iter().cloned().collect::<Vec<_>>();
+        // The retries belong to the operation that led to the handshake, not to the subscribes.
+        let retries = (
+            self.advice_reconnects,
+            self.transport_retries,
+            self.retries_started,
         );
-        req.send()
-            .map_err(|_| Error::new("Could not send request to server"))
+
+        for channel in channels {
+            log::debug!("Resubscribing to {}", channel);
+            let resps = self.subscribe(&channel);
+
+            (
+                self.advice_reconnects,
+                self.transport_retries,
+                self.retries_started,
+            ) = retries;
+            resps?;
+        }
+        Ok(())
+    }
+
+    /// Checks that the advice from the server can be followed and waits before following it.
+    /// Returns the request to send next.
+    fn follow_advice(&mut self, advice: &Advice, error: Option<&str>) -> Result<Step, Error> {
+        log::debug!("Following advice from server");
+        self.store_advice(advice);
+        let step = advised_step(advice, error)?;
+
+        self.check_reconnect_duration(error)?;
+        if self.advice_reconnects < self.max_advice_reconnects {
+            self.advice_reconnects += 1;
+            log::debug!("Retry n°{}", self.advice_reconnects);
+            self.metrics.incr(metrics::RETRIES);
+            if let Some(ref on_reconnect) = self.on_reconnect {
+                on_reconnect();
+            }
+            self.wait_for_interval(Some(advice), self.remaining_reconnect_duration());
+            if let Some(backoff) = self.backoff {
+                backoff.wait(self.advice_reconnects, self.remaining_reconnect_duration());
+            }
+            // The waits may have used up the rest of the duration.
+            self.check_reconnect_duration(error)?;
+            Ok(step)
+        } else {
+            Err(Error::new(
+                ErrorKind::MaxRetriesReached,
+                error.unwrap_or("Max retries reached"),
+            ))
+        }
     }
 
-    fn retry(&mut self) -> Result<Vec<Response>, Error> {
-        self.actual_retries += 1;
-        log::debug!("Attempt n°{}", self.actual_retries);
+    /// Handles the error returned by the cometd server. If possible, returns the request to send
+    /// to recover from it according to the client configuration. Otherwise, or once the retries
+    /// are exhausted, the original error is returned.
+    fn handle_error(&mut self, resp: &ErroredResponse) -> Result<Step, Error> {
+        let advice = recovery_advice(resp, &self.version, self.rehandshake_on_unknown_client)?;
 
-        match &self.client_id {
-            Some(client_id) => {
-                let resp = self.send_request(&ConnectPayload {
-                    channel: "/meta/connect",
-                    client_id: &client_id,
-                    connection_type: "long-polling",
-                })?;
+        self.follow_advice(&advice, Some(&resp.error))
+    }
 
-                self.handle_response(resp)
-            }
-            None => Err(Error::new("No client id set for connect")),
+    /// Keeps the cookies set by a response, see [merge_cookies]. Since the cookie header is
+    /// built from them, the cookie store of the http client is not used.
+    fn update_cookies(&mut self, cookies: Vec<String>) {
+        if cookies.is_empty() {
+            return;
         }
+        merge_cookies(&mut self.cookies, cookies);
+        self.sync_session();
     }
 
-    fn retry_handshake(&mut self) -> Result<Vec<Response>, Error> {
-        self.actual_retries += 1;
-        log::debug!("Attempt n°{}", self.actual_retries);
-
-        let resp = self.send_request(&HandshakePayload {
-            channel: "/meta/handshake",
-            version: COMETD_VERSION,
-            supported_connection_types: COMETD_SUPPORTED_TYPES.to_vec(),
-        })?;
+    /// Keeps the replay id of an event received on a channel the replay ids are tracked for.
+    fn update_replay_id(&mut self, resp: &DeliveryResponse) {
+        let replay_id = resp
+            .data
+            .pointer("/event/replayId")
+            .and_then(serde_json::Value::as_i64);
 
-        self.handle_response(resp)
+        if let (Some(replay_id), Some(stored)) = (replay_id, self.replay.get_mut(&resp.channel)) {
+            *stored = replay_id;
+        }
     }
 
-    fn handle_advice(
+    /// Handles the messages of a single response body. Returns the successful messages along
+    /// with the request to send next if the server advised to reconnect or handshake.
+    fn process_response(
         &mut self,
-        advice: &Advice,
-        error: Option<&str>,
-    ) -> Result<Vec<Response>, Error> {
-        log::debug!("Following advice from server");
-        match advice.reconnect {
-            Reconnect::Handshake => {
-                if self.actual_retries <= self.max_retries {
-                    match self.retry_handshake() {
-                        Ok(_) => self.retry(),
-                        Err(err) => Err(err),
+        resp: RawResponse,
+    ) -> Result<(Vec<Response>, Option<Step>), Error> {
+        let RawResponse { body, cookies } = resp;
+        let expected_id = self.message_id.to_string();
+        let mut responses = vec![];
+        let mut next = None;
+        let mut stop = None;
+
+        #[cfg(feature = "websocket")]
+        self.keep_unrelated_messages();
+        self.update_cookies(cookies);
+        if self.strict_parsing {
+            if let Err(err) = check_known_fields(&body) {
+                log::warn!("{}", err);
+                return Err(err.with_body(&body));
+            }
+        }
+        let parsed = parse_body(&body, self.log_bodies)?;
+        // Checked before any message is handled, so that the replay and ack ids do not move
+        // past messages that are never returned.
+        let count = match parsed {
+            ParsedBody::Errored(ref resps) => resps.len(),
+            ParsedBody::Successful(ref resps) => resps.len(),
+        };
+        if let Some(max) = self.max_messages_per_poll.filter(|max| count > *max) {
+            return Err(Error::new(
+                ErrorKind::Protocol,
+                &format!(
+                    "The server sent {} messages, more than the limit of {}",
+                    count, max
+                ),
+            ));
+        }
+        match parsed {
+            ParsedBody::Errored(resps) => {
+                for resp in resps.into_iter() {
+                    check_id(&resp.channel, resp.id.as_deref(), &expected_id)?;
+                    if next.is_some() {
+                        continue;
+                    }
+                    match self.handle_error(&resp) {
+                        Ok(step) => next = Some(step),
+                        Err(err)
+                            if self.partial_results
+                                && err.kind() == ErrorKind::Protocol
+                                && resp.channel != channel::SUBSCRIBE =>
+                        {
+                            self.partial_errors.push(err)
+                        }
+                        Err(err) => return Err(err),
                     }
-                } else {
-                    Err(Error::new(error.unwrap_or("Max retries reached")))
                 }
             }
-            Reconnect::Retry => {
-                if self.actual_retries <= self.max_retries {
-                    self.retry()
-                } else {
-                    Err(Error::new(error.unwrap_or("Max retries reached")))
+            ParsedBody::Successful(resps) => {
+                for resp in resps.into_iter() {
+                    check_id(resp.channel(), resp.id(), &expected_id)?;
+                    match resp.advice() {
+                        Some(ref advice) if resp.is_successful() == Some(false) => {
+                            if next.is_none() && stop.is_none() {
+                                match self.follow_advice(advice, None) {
+                                    Ok(step) => next = Some(step),
+                                    Err(err) if err.kind() == ErrorKind::ServerAdvisedStop => {
+                                        stop = Some(err)
+                                    }
+                                    Err(err) => return Err(err),
+                                }
+                            }
+                            continue;
+                        }
+                        Some(ref advice) => self.store_advice(advice),
+                        None => {}
+                    }
+                    if let Response::Basic(BasicResponse {
+                        successful: false,
+                        error: Some(ref error),
+                        ..
+                    }) = resp
+                    {
+                        // A rejected subscription is reported by subscribe itself.
+                        if self.partial_results && resp.channel() != channel::SUBSCRIBE {
+                            self.partial_errors
+                                .push(Error::new(ErrorKind::Protocol, error));
+                            continue;
+                        }
+                    }
+                    if let Response::Delivery(ref resp) = resp {
+                        self.update_replay_id(resp);
+                    }
+                    if let (channel::CONNECT, Some(ext)) = (resp.channel(), resp.ext()) {
+                        if self.timesync {
+                            self.update_timesync(ext);
+                        }
+                        if let (true, Some(ack_id)) = (self.ack_extension, ext["ack"].as_i64()) {
+                            self.ack_id = Some(ack_id);
+                        }
+                    }
+                    if let Response::Handshake(ref resp) = resp {
+                        let advertised = self.advertised_connection_types();
+                        let negotiated = advertised.iter().find(|connection_type| {
+                            resp.supported_connection_types.is_empty()
+                                || resp.supported_connection_types.contains(connection_type)
+                        });
+                        match negotiated {
+                            Some(connection_type) => {
+                                self.negotiated_connection_type = Some(connection_type.clone())
+                            }
+                            None => {
+                                let message = format!(
+                                    "The server does not support the {} connection type, only {}",
+                                    advertised.join(" or "),
+                                    resp.supported_connection_types.join(", ")
+                                );
+
+                                return Err(Error::new(ErrorKind::Protocol, &message));
+                            }
+                        }
+                        self.server_supported_types = resp.supported_connection_types.clone();
+                        self.client_id = Some(resp.client_id.clone());
+                        // The ack ids are specific to the session.
+                        self.ack_id = None;
+                        self.state = ClientState::Handshaked;
+                        self.sync_session();
+                        self.metrics.incr(metrics::HANDSHAKES);
+                        if let Some(ref on_handshake) = self.on_handshake {
+                            on_handshake(resp);
+                        }
+                    }
+                    responses.push(resp);
                 }
             }
-            Reconnect::None => {
-                log::debug!(
-                    "Not retrying because the server answered not to reconnect nor handshake"
-                );
-                Err(Error::new(error.unwrap_or(
-                    "Service advised not to reconnect nor handshake",
-                )))
+        }
+        if let Some(err) = stop {
+            if responses.is_empty() {
+                return Err(err);
             }
+            self.stop_session();
         }
+        Ok((responses, next))
     }
 
-    /// Handles the error returned by the cometd server. If possible, it will
-    /// automatically retry according to the client configuration. If it still
-    /// fails after the retries, the original error will be returned.
-    fn handle_error(&mut self, resp: &ErroredResponse) -> Result<Vec<Response>, Error> {
-        match resp.advice {
-            Some(ref advice) => self.handle_advice(advice, Some(&resp.error)),
-            None => {
-                log::debug!("Not retrying because the server did not provide advice");
-                Err(Error::new(&resp.error))
-            }
-        }
+    /// Forgets the session once the server advised not to reconnect nor handshake, so that the
+    /// messages received along with the advice can still be returned.
+    fn stop_session(&mut self) {
+        log::info!("Session stopped as advised by the server");
+        self.client_id = None;
+        self.state = ClientState::Disconnected;
+        self.sync_session();
     }
 
-    fn handle_response(&mut self, mut resp: ReqwestReponse) -> Result<Vec<Response>, Error> {
-        let body = resp
-            .text()
-            .map_err(|_| Error::new("Could not get the response body"))?;
-        let cookies = resp
-            .cookies()
-            .map(|c| c.value().to_owned())
-            .collect::<Vec<_>>();
+    /// Handles a response from the server, following its advice until it stops advising to
+    /// reconnect or the retries are exhausted. The advice is followed in a loop rather than
+    /// recursively so that the stack does not grow with the number of retries. When the
+    /// response is the one of a `handshake`, the advice is followed by handshaking again and
+    /// the responses of the successful handshake are returned.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug", skip_all, fields(client_id = self.client_id.as_deref())
+        )
+    )]
+    fn handle_response(
+        &mut self,
+        resp: RawResponse,
+        handshake: bool,
+    ) -> Result<Vec<Response>, Error> {
+        let mut resp = resp;
         let mut responses = vec![];
+        // The responses to a handshake sent to follow an advice are not returned, only the ones
+        // of the connect sent after it.
+        let mut handshaking = false;
 
-        log::debug!("Received response from cometd server: {:?}", body);
-        match serde_json::from_str::<Vec<ErroredResponse>>(&body) {
-            Ok(resps) => {
-                for resp in resps.into_iter() {
-                    let resps = self.handle_error(&resp)?;
-
-                    for resp in resps.into_iter() {
-                        responses.push(resp);
-                    }
+        loop {
+            let processed = self.process_response(resp);
+            if let Err(ref err) = processed {
+                self.metrics.incr(metrics::error_counter(err.kind()));
+            }
+            let (resps, next) = match processed {
+                Err(err) if err.kind() == ErrorKind::ServerAdvisedStop && !responses.is_empty() => {
+                    self.stop_session();
+                    return Ok(responses);
                 }
-                Ok(responses)
+                resps => resps?,
+            };
+            if !handshaking || handshake {
+                self.buffer_messages(&resps);
+                responses.extend(resps);
             }
-            Err(_) => match serde_json::from_str::<Vec<Response>>(&body) {
-                Ok(resps) => {
-                    let mut responses = vec![];
 
-                    for resp in resps.into_iter() {
-                        if let Some(ref advice) = resp.advice() {
-                            for resp in self.handle_advice(advice, None)? {
-                                responses.push(resp);
-                            }
-                        } else {
-                            if let Response::Handshake(ref resp) = resp {
-                                self.client_id = Some(resp.client_id.clone());
-                                self.cookies = cookies.clone();
-                            }
-                            responses.push(resp);
-                        }
-                    }
-                    Ok(responses)
+            let next = match next {
+                Some(_) if handshake => Step::Handshake,
+                Some(next) => next,
+                None if handshake => return Ok(responses),
+                None if handshaking => {
+                    handshaking = false;
+                    self.resubscribe()?;
+                    Step::Connect
                 }
-                Err(_) => {
-                    log::error!(
-                        "Handle response failed with the following server response: {:?}",
-                        body
-                    );
-                    Err(Error::new("Could not parse response"))
+                None => return Ok(responses),
+            };
+            resp = match next {
+                Step::Connect => match self.retried_request.clone() {
+                    Some(body) => self.resend(body)?,
+                    None => self.send_connect()?,
+                },
+                Step::Handshake => {
+                    handshaking = true;
+                    self.send_handshake()?
                 }
-            },
+            };
         }
     }
 
     fn handshake(&mut self) -> Result<Vec<Response>, Error> {
-        let resps = self.retry_handshake();
+        self.reset_retries();
+        let resps = self.retry_handshake()?;
 
-        self.actual_retries = 0;
-        resps
+        // One at a time, so that the subscriptions after a failed one stay queued.
+        while self.client_id.is_some() && !self.pending_subscriptions.is_empty() {
+            let channel = self.pending_subscriptions.remove(0);
+
+            log::debug!("Subscribing to {} now that the client handshaked", channel);
+            self.subscribe(&channel)?;
+        }
+        Ok(resps)
     }
 
     /// The cometd connect method. It will hang for a response from the server according
@@ -258,44 +2043,263 @@ impl Client {
     /// If an errored response is received but an advice is provided by the server, the client
     /// will try to follow this advice and re-attemp the connection. If the maximum number of retries
     /// is reached and the response still does not succeed, it will return an error.
+    /// If the request cannot reach the server, it is sent again up to the maximum number of
+    /// transport retries, waiting for the backoff in between if one is set.
+    /// If the server advises not to reconnect along with messages, those messages are returned
+    /// and the client forgets the session, as after a [disconnect](Client::disconnect).
     ///
     /// # Errors
     ///
+    /// The cometd server could not be reached after the transport retries.
     /// The cometd server's response could not be parsed.
     /// The cometd server returned a response that indicated an error and the request could not be
     /// retried or the maximum number of retries has been reached.
+    /// The cometd server sent more messages than the [limit](Client::set_max_messages_per_poll).
+    /// The request was cancelled through a [CancelHandle](CancelHandle).
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(client_id = self.client_id.as_deref()))
+    )]
     pub fn connect(&mut self) -> Result<Vec<Response>, Error> {
-        let resps = self.retry();
+        self.reset_retries();
+        let resps = match self.retry() {
+            Err(ref err) if self.treat_timeout_as_empty && err.is_timeout() => {
+                log::debug!("Connect timed out without any message");
+                Ok(vec![])
+            }
+            resps => resps,
+        };
+
+        if resps.is_ok() && self.client_id.is_some() {
+            self.state = ClientState::Connected;
+        }
+        resps
+    }
+
+    /// The cometd connect method, waiting for the server at most `timeout` instead of the
+    /// current timeout of the client. The timeout only applies to this call and the retries
+    /// it may lead to. See [connect](Client::connect).
+    ///
+    /// # Errors
+    ///
+    /// The cometd server did not answer before the timeout.
+    /// The cometd server's response could not be parsed.
+    /// The cometd server returned a response that indicated an error and the request could not be
+    /// retried or the maximum number of retries has been reached.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(client_id = self.client_id.as_deref()))
+    )]
+    pub fn connect_with_timeout(&mut self, timeout: Duration) -> Result<Vec<Response>, Error> {
+        self.timeout_override = Some(timeout);
+        let resps = self.connect();
 
-        self.actual_retries = 0;
+        self.timeout_override = None;
         resps
     }
 
     /// The cometd disconnect method.
     /// If one or several sucess responses are returned to the request, it will return a `Vec`
     /// containing those responses.
+    /// Once disconnected, the client forgets its client id, the channels it was subscribed to
+    /// and the session cookies, so it must be initialized again before being used.
     ///
     /// # Errors
     ///
     /// The cometd server's response could not be parsed.
     /// The cometd server returned a response that indicated an error and the request could not be
     /// retried or the maximum number of retries has been reached.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(client_id = self.client_id.as_deref()))
+    )]
     pub fn disconnect(&mut self) -> Result<Vec<Response>, Error> {
-        match &self.client_id {
+        self.reset_retries();
+        match self.client_id.clone() {
             Some(client_id) => {
-                let resp = self.send_request(&DisconnectPayload {
-                    channel: "/meta/disconnect",
-                    client_id,
-                })?;
+                let id = self.next_message_id();
+                let resp = self.send_request(
+                    Some(channel::DISCONNECT),
+                    &DisconnectPayload {
+                        channel: channel::DISCONNECT,
+                        client_id: &client_id,
+                        id,
+                    },
+                )?;
+
+                let resps = self.handle_response(resp, false)?;
+
+                self.client_id = None;
+                self.subscriptions.clear();
+                self.cookies.clear();
+                self.pending_messages.clear();
+                self.state = ClientState::Disconnected;
+                self.sync_session();
+                Ok(resps)
+            }
+            None => Err(Error::new(
+                ErrorKind::NotHandshaked,
+                "No client id set for disconnect",
+            )),
+        }
+    }
+
+    /// Listens for messages on the subscribed channels. It will repeatedly issue connect
+    /// requests and call `callback` with every delivery or publish message received, waiting
+    /// for the interval advised by the server between two requests.
+    /// It returns once the session ends, that is when the server answers with a disconnect
+    /// message.
+    ///
+    /// # Errors
+    ///
+    /// The client has not been initialized.
+    /// The cometd server's response could not be parsed.
+    /// The cometd server returned a response that indicated an error and the request could not be
+    /// retried or the maximum number of retries has been reached.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(client_id = self.client_id.as_deref()))
+    )]
+    pub fn listen<F>(&mut self, mut callback: F) -> Result<(), Error>
+    where
+        F: FnMut(Response),
+    {
+        if self.client_id.is_none() {
+            return Err(Error::new(
+                ErrorKind::NotHandshaked,
+                "No client id set for listen",
+            ));
+        }
+        let mut last_message = Instant::now();
+
+        while self.client_id.is_some() {
+            let resps = self.poll_messages()?;
+
+            self.check_idle(!resps.is_empty(), &mut last_message);
+            for resp in resps {
+                callback(resp);
+            }
+            self.wait_for_interval(self.advice.as_ref(), None);
+        }
+        Ok(())
+    }
+
+    /// Listens for messages like [listen](Client::listen) until `stop` is set. The flag is
+    /// checked between two connect requests, so a long poll in progress when it is set is
+    /// completed and its messages handed to `callback` first. The client then disconnects
+    /// from the server.
+    ///
+    /// # Errors
+    ///
+    /// The client has not been initialized.
+    /// The cometd server's response could not be parsed.
+    /// The cometd server returned a response that indicated an error and the request could not be
+    /// retried or the maximum number of retries has been reached.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(client_id = self.client_id.as_deref()))
+    )]
+    pub fn listen_until<F>(&mut self, mut callback: F, stop: Arc<AtomicBool>) -> Result<(), Error>
+    where
+        F: FnMut(Response),
+    {
+        if self.client_id.is_none() {
+            return Err(Error::new(
+                ErrorKind::NotHandshaked,
+                "No client id set for listen",
+            ));
+        }
+        let mut last_message = Instant::now();
+
+        while self.client_id.is_some() && !stop.load(Ordering::SeqCst) {
+            let resps = self.poll_messages()?;
+
+            self.check_idle(!resps.is_empty(), &mut last_message);
+            for resp in resps {
+                callback(resp);
+            }
+            if !stop.load(Ordering::SeqCst) {
+                self.wait_for_interval(self.advice.as_ref(), None);
+            }
+        }
+        if self.client_id.is_some() {
+            log::info!("Stopped listening, disconnecting");
+            self.disconnect()?;
+        }
+        Ok(())
+    }
+
+    /// Invokes the idle callback when no message has been received since `last_message` for
+    /// longer than the idle timeout, then starts a new idle period.
+    fn check_idle(&self, received: bool, last_message: &mut Instant) {
+        let idle_timeout = match self.idle_timeout {
+            Some(idle_timeout) => idle_timeout,
+            None => return,
+        };
+
+        if received {
+            *last_message = Instant::now();
+            return;
+        }
+        let elapsed = last_message.elapsed();
+
+        if elapsed >= idle_timeout {
+            log::warn!("No message delivered for {}ms", elapsed.as_millis());
+            if let Some(ref on_idle) = self.on_idle {
+                on_idle(elapsed);
+            }
+            *last_message = Instant::now();
+        }
+    }
+
+    /// Returns an iterator over the messages received on the subscribed channels. Like
+    /// [listen](Client::listen), it repeatedly issues connect requests and yields every
+    /// delivery or publish message received.
+    /// It ends once the session ends, or after yielding an error if a request fails.
+    pub fn messages(&mut self) -> Messages<'_> {
+        Messages {
+            client: self,
+            pending: VecDeque::new(),
+            polled: false,
+            failed: false,
+        }
+    }
+
+    /// Issues a connect request and returns the delivery and publish messages received.
+    /// If the server closed the session, the client is marked as disconnected.
+    fn poll_messages(&mut self) -> Result<Vec<Response>, Error> {
+        if !self.pending_messages.is_empty() {
+            return Ok(self.pending_messages.drain(..).collect());
+        }
+        let resps = self.connect()?;
+
+        Ok(self.collect_messages(resps))
+    }
+
+    /// Keeps the delivery and publish messages among the responses to a connect request,
+    /// noting when the server closed the session.
+    fn collect_messages(&mut self, resps: Vec<Response>) -> Vec<Response> {
+        let mut messages = vec![];
 
-                self.handle_response(resp)
+        for resp in resps {
+            match resp {
+                Response::Delivery(_) | Response::Publish(_) => messages.push(resp),
+                Response::Basic(ref resp) if resp.channel == channel::DISCONNECT => {
+                    log::info!("Session closed by the server");
+                    self.client_id = None;
+                    self.state = ClientState::Disconnected;
+                    self.sync_session();
+                }
+                _ => {}
             }
-            None => Err(Error::new("No client id set for disconnect")),
         }
+        messages
     }
 
     /// Init the cometd client. It will attempt to establish a handshake between
-    /// the client and the server so it can make further requests.
+    /// the client and the server so it can make further requests. The subscriptions
+    /// requested before are then sent.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
     pub fn init(&mut self) -> Result<Vec<Response>, Error> {
         let resps = self.handshake()?;
 
@@ -303,31 +2307,170 @@ impl Client {
         Ok(resps)
     }
 
+    /// Handshakes again with the server, discarding the current session, and subscribes
+    /// again to the channels the client was subscribed to. Useful when the application knows
+    /// the session is dead without the server having advised so.
+    ///
+    /// # Errors
+    ///
+    /// The handshake failed, see [init](Client::init).
+    /// The subscription to one of the channels failed.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub fn rehandshake(&mut self) -> Result<Vec<Response>, Error> {
+        log::info!("Handshaking again on request");
+        self.client_id = None;
+        self.state = ClientState::Disconnected;
+        let resps = self.handshake()?;
+
+        self.resubscribe()?;
+        Ok(resps)
+    }
+
+    /// Init the cometd client like [init](Client::init), but returns the handshake response
+    /// of the server directly.
+    ///
+    /// # Errors
+    ///
+    /// The handshake failed, see [init](Client::init).
+    /// The server did not answer with a successful handshake response.
+    pub fn init_handshake(&mut self) -> Result<HandshakeResponse, Error> {
+        self.init()?
+            .into_iter()
+            .find_map(|resp| match resp {
+                Response::Handshake(resp) if resp.successful => Some(resp),
+                _ => None,
+            })
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::Protocol,
+                    "The server did not answer with a successful handshake",
+                )
+            })
+    }
+
+    /// Inits the client and issues the first connect request, returning the responses of
+    /// both. See [init](Client::init) and [connect](Client::connect).
+    ///
+    /// # Errors
+    ///
+    /// The handshake failed, in which case no connect request is sent.
+    /// The cometd server's response could not be parsed.
+    /// The cometd server returned a response that indicated an error and the request could not be
+    /// retried or the maximum number of retries has been reached.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub fn start(&mut self) -> Result<Vec<Response>, Error> {
+        let mut resps = self.init()?;
+
+        resps.extend(self.connect()?);
+        Ok(resps)
+    }
+
     /// The cometd subscribe method. It will ask the server to subscribe to a certain channel and therefore
     /// be updated when something is posted on this channel.
     /// If one or several sucess responses are returned to the request, it will return a `Vec`
     /// containing those responses.
     /// If an errored response is received but an advice is provided by the server, the client
-    /// will try to follow this advice and re-attemp the connection. If the maximum number of retries
+    /// will try to follow this advice and send the subscribe again. If the maximum number of retries
     /// is reached and the response still does not succeed, it will return an error.
+    /// If the client has not handshaked yet, the subscription is queued and sent right after
+    /// the next successful [init](Client::init), and an empty `Vec` is returned. If a queued
+    /// subscription fails, `init` returns its error and the ones queued after it are kept for
+    /// the next handshake.
     ///
     /// # Errors
     ///
+    /// The channel name is invalid.
     /// The cometd server's response could not be parsed.
     /// The cometd server returned a response that indicated an error and the request could not be
     /// retried or the maximum number of retries has been reached.
+    /// The cometd server did not accept the subscription.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all, fields(client_id = self.client_id.as_deref(), channel = subscription)
+        )
+    )]
     pub fn subscribe(&mut self, subscription: &str) -> Result<Vec<Response>, Error> {
-        match &self.client_id {
+        self.reset_retries();
+        validate_channel(subscription, ChannelKind::Subscribe)?;
+        if self.client_id.is_none() {
+            log::debug!(
+                "Queuing the subscription to {} until the handshake",
+                subscription
+            );
+            if !self.pending_subscriptions.iter().any(|c| c == subscription) {
+                self.pending_subscriptions.push(subscription.to_owned());
+            }
+            return Ok(vec![]);
+        }
+        let ext = self.with_replay_ext(None);
+        match self.client_id.clone() {
             Some(client_id) => {
-                let resp = self.send_request(&SubscribeTopicPayload {
-                    channel: "/meta/subscribe",
-                    client_id,
+                let id = self.next_message_id();
+                let resps = self.send_retryable(&SubscribeTopicPayload {
+                    channel: channel::SUBSCRIBE,
+                    client_id: &client_id,
                     subscription,
+                    id,
+                    ext,
                 })?;
 
-                self.handle_response(resp)
+                check_subscription(&resps, subscription)?;
+                self.subscriptions.insert(subscription.to_owned());
+                Ok(resps)
+            }
+            None => Err(Error::new(
+                ErrorKind::NotHandshaked,
+                "No client id set for subscribe",
+            )),
+        }
+    }
+
+    /// Subscribes to several channels at once. A single subscribe message is sent with
+    /// all the channels, reducing the number of round trips. Depending on the server, the
+    /// returned `Vec` either contains one response for all the channels or one per channel.
+    ///
+    /// # Errors
+    ///
+    /// The channel name is invalid.
+    /// The cometd server's response could not be parsed.
+    /// The cometd server returned a response that indicated an error and the request could not be
+    /// retried or the maximum number of retries has been reached.
+    /// The cometd server did not accept the subscription to one of the channels.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all, fields(client_id = self.client_id.as_deref(), channels = ?subscriptions)
+        )
+    )]
+    pub fn subscribe_many(&mut self, subscriptions: &[&str]) -> Result<Vec<Response>, Error> {
+        self.reset_retries();
+        for subscription in subscriptions {
+            validate_channel(subscription, ChannelKind::Subscribe)?;
+        }
+        let ext = self.with_replay_ext(None);
+        match self.client_id.clone() {
+            Some(client_id) => {
+                let id = self.next_message_id();
+                let resps = self.send_retryable(&SubscribeTopicPayload {
+                    channel: channel::SUBSCRIBE,
+                    client_id: &client_id,
+                    subscription: subscriptions,
+                    id,
+                    ext,
+                })?;
+
+                for subscription in subscriptions {
+                    check_subscription(&resps, subscription)?;
+                }
+                self.subscriptions
+                    .extend(subscriptions.iter().map(|channel| (*channel).to_owned()));
+                Ok(resps)
             }
-            None => Err(Error::new("No client id set for subscribe")),
+            None => Err(Error::new(
+                ErrorKind::NotHandshaked,
+                "No client id set for subscribe",
+            )),
         }
     }
 
@@ -336,53 +2479,363 @@ impl Client {
     /// If one or several sucess responses are returned to the request, it will return a `Vec`
     /// containing those responses.
     /// If an errored response is received but an advice is provided by the server, the client
-    /// will try to follow this advice and re-attemp the connection. If the maximum number of retries
+    /// will try to follow this advice and send the unsubscribe again. If the maximum number of retries
     /// is reached and the response still does not succeed, it will return an error.
     ///
     /// # Errors
     ///
+    /// The channel name is invalid.
     /// The cometd server's response could not be parsed.
     /// The cometd server returned a response that indicated an error and the request could not be
     /// retried or the maximum number of retries has been reached.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all, fields(client_id = self.client_id.as_deref(), channel = subscription)
+        )
+    )]
     pub fn unsubscribe(&mut self, subscription: &str) -> Result<Vec<Response>, Error> {
-        match &self.client_id {
+        self.reset_retries();
+        validate_channel(subscription, ChannelKind::Subscribe)?;
+        match self.client_id.clone() {
             Some(client_id) => {
-                let resp = self.send_request(&SubscribeTopicPayload {
-                    channel: "/meta/unsubscribe",
-                    client_id,
+                let id = self.next_message_id();
+                let resps = self.send_retryable(&SubscribeTopicPayload {
+                    channel: channel::UNSUBSCRIBE,
+                    client_id: &client_id,
                     subscription,
+                    id,
+                    ext: None,
+                })?;
+
+                self.subscriptions.remove(subscription);
+                Ok(resps)
+            }
+            None => Err(Error::new(
+                ErrorKind::NotHandshaked,
+                "No client id set for unsubscribe",
+            )),
+        }
+    }
+
+    /// Unsubscribes from every channel the client is subscribed to, within a single message,
+    /// and forgets about them. Useful to shut down gracefully before disconnecting. Nothing is
+    /// sent if the client is not subscribed to any channel.
+    ///
+    /// # Errors
+    ///
+    /// The cometd server's response could not be parsed.
+    /// The cometd server returned a response that indicated an error and the request could not be
+    /// retried or the maximum number of retries has been reached.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(client_id = self.client_id.as_deref()))
+    )]
+    pub fn unsubscribe_all(&mut self) -> Result<Vec<Response>, Error> {
+        self.reset_retries();
+        if self.subscriptions.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut subscriptions = self.subscriptions.iter().cloned().collect::<Vec<_>>();
+        subscriptions.sort();
+        match self.client_id.clone() {
+            Some(client_id) => {
+                let id = self.next_message_id();
+                let resps = self.send_retryable(&SubscribeTopicPayload {
+                    channel: channel::UNSUBSCRIBE,
+                    client_id: &client_id,
+                    subscription: &subscriptions,
+                    id,
+                    ext: None,
                 })?;
 
-                self.handle_response(resp)
+                self.subscriptions.clear();
+                Ok(resps)
             }
-            None => Err(Error::new("No client id set for unsubscribe")),
+            None => Err(Error::new(
+                ErrorKind::NotHandshaked,
+                "No client id set for unsubscribe",
+            )),
         }
     }
 
     /// The cometd plublish method. It will ask the server to publish a message to a certain channel.
     /// If one or several sucess responses are returned to the request, it will return a `Vec`
-    /// containing those responses.
+    /// containing those responses, in the order the server sent them. Messages delivered along
+    /// with the acknowledgement are kept, which can be told apart with
+    /// [matches_channel](Response::matches_channel) and
+    /// [is_successful](Response::is_successful).
     /// If an errored response is received but an advice is provided by the server, the client
-    /// will try to follow this advice and re-attemp the connection. If the maximum number of retries
-    /// is reached and the response still does not succeed, it will return an error.
+    /// will try to follow this advice and publish the message again. If the maximum number of
+    /// retries is reached and the response still does not succeed, it will return an error.
     ///
     /// # Errors
     ///
+    /// The channel name is invalid.
     /// The cometd server's response could not be parsed.
     /// The cometd server returned a response that indicated an error and the request could not be
     /// retried or the maximum number of retries has been reached.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all, fields(client_id = self.client_id.as_deref(), channel = channel)
+        )
+    )]
     pub fn publish(&mut self, channel: &str, data: impl Serialize) -> Result<Vec<Response>, Error> {
-        match &self.client_id {
+        self.send_publish(channel, data, None)
+            .map(|(_, resps)| resps)
+    }
+
+    /// Publishes a message like [publish](Client::publish) with an `ext` field, such as
+    /// the replay ids expected by Salesforce.
+    ///
+    /// # Errors
+    ///
+    /// The channel name is invalid.
+    /// The cometd server's response could not be parsed.
+    /// The cometd server returned a response that indicated an error and the request could not be
+    /// retried or the maximum number of retries has been reached.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all, fields(client_id = self.client_id.as_deref(), channel = channel)
+        )
+    )]
+    pub fn publish_with_ext(
+        &mut self,
+        channel: &str,
+        data: impl Serialize,
+        ext: serde_json::Value,
+    ) -> Result<Vec<Response>, Error> {
+        self.send_publish(channel, data, Some(&ext))
+            .map(|(_, resps)| resps)
+    }
+
+    /// Publishes a message like [publish](Client::publish) but returns the id of the message
+    /// sent, so it can be matched against the eventual
+    /// [PublishResponse](crate::response::PublishResponse).
+    ///
+    /// # Errors
+    ///
+    /// The channel name is invalid.
+    /// The cometd server's response could not be parsed.
+    /// The cometd server returned a response that indicated an error and the request could not be
+    /// retried or the maximum number of retries has been reached.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all, fields(client_id = self.client_id.as_deref(), channel = channel)
+        )
+    )]
+    pub fn publish_tracked(
+        &mut self,
+        channel: &str,
+        data: impl Serialize,
+    ) -> Result<String, Error> {
+        self.send_publish(channel, data, None).map(|(id, _)| id)
+    }
+
+    /// Publishes several messages, possibly on different channels, within a single request as
+    /// allowed by the Bayeux protocol. Every message gets its own id, in the order of
+    /// `messages`, which the responses of the server refer to.
+    ///
+    /// # Errors
+    ///
+    /// One of the channel names is invalid.
+    /// The cometd server's response could not be parsed.
+    /// The cometd server returned a response that indicated an error and the request could not be
+    /// retried or the maximum number of retries has been reached.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all, fields(client_id = self.client_id.as_deref(), messages = messages.len())
+        )
+    )]
+    pub fn publish_batch(
+        &mut self,
+        messages: &[(&str, serde_json::Value)],
+    ) -> Result<Vec<Response>, Error> {
+        self.reset_retries();
+        for (channel, _) in messages {
+            validate_channel(channel, ChannelKind::Publish)?;
+        }
+        if messages.is_empty() {
+            return Ok(vec![]);
+        }
+        match self.client_id.clone() {
+            Some(client_id) => {
+                let payloads = messages
+                    .iter()
+                    .map(|(channel, data)| PublishPayload {
+                        channel,
+                        client_id: &client_id,
+                        data,
+                        id: self.next_message_id(),
+                        ext: None,
+                    })
+                    .collect::<Vec<_>>();
+                self.send_retryable(&payloads)
+            }
+            None => Err(Error::new(
+                ErrorKind::NotHandshaked,
+                "No client id set for publish",
+            )),
+        }
+    }
+
+    /// Publishes a message then waits for a message to be delivered on `reply_channel`, which
+    /// the client must be subscribed to, for at most `timeout`. This allows request/response
+    /// exchanges over channels. The other messages received meanwhile are kept and returned
+    /// first by [listen](Client::listen) and [messages](Client::messages).
+    ///
+    /// # Errors
+    ///
+    /// The channel name is invalid.
+    /// No reply was delivered before the timeout or the server closed the session.
+    /// The cometd server's response could not be parsed.
+    /// The cometd server returned a response that indicated an error and the request could not be
+    /// retried or the maximum number of retries has been reached.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            skip_all,
+            fields(
+                client_id = self.client_id.as_deref(),
+                channel = publish_channel,
+                reply_channel = reply_channel
+            )
+        )
+    )]
+    pub fn publish_and_wait(
+        &mut self,
+        publish_channel: &str,
+        data: impl Serialize,
+        reply_channel: &str,
+        timeout: Duration,
+    ) -> Result<Response, Error> {
+        let deadline = Instant::now() + timeout;
+        let no_reply = || {
+            Error::new(
+                ErrorKind::Transport,
+                &format!("No reply received on {} before the timeout", reply_channel),
+            )
+        };
+        let (id, resps) = self.send_publish(publish_channel, data, None)?;
+        // The reply may come along with the acknowledgment of the published message.
+        let mut messages = resps
+            .into_iter()
+            .filter(|resp| matches!(resp, Response::Delivery(_)) || resp.id() != Some(&id))
+            .collect::<Vec<_>>();
+
+        loop {
+            let mut reply = None;
+            for message in messages {
+                match message {
+                    Response::Delivery(ref delivery)
+                        if reply.is_none() && delivery.channel == reply_channel =>
+                    {
+                        reply = Some(message)
+                    }
+                    Response::Delivery(_) | Response::Publish(_) => {
+                        self.pending_messages.push_back(message)
+                    }
+                    _ => {}
+                }
+            }
+            if let Some(reply) = reply {
+                return Ok(reply);
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(no_reply());
+            }
+            if self.client_id.is_none() {
+                return Err(Error::new(
+                    ErrorKind::NotHandshaked,
+                    "The session was closed before a reply was received",
+                ));
+            }
+            let resps = match self.connect_with_timeout(remaining) {
+                Err(ref err) if err.is_timeout() => return Err(no_reply()),
+                resps => resps?,
+            };
+            messages = self.collect_messages(resps);
+        }
+    }
+
+    fn send_publish(
+        &mut self,
+        channel: &str,
+        data: impl Serialize,
+        ext: Option<&serde_json::Value>,
+    ) -> Result<(String, Vec<Response>), Error> {
+        self.reset_retries();
+        validate_channel(channel, ChannelKind::Publish)?;
+        match self.client_id.clone() {
             Some(client_id) => {
-                let resp = self.send_request(&PublishPayload {
+                let id = self.next_message_id();
+                let payload = PublishPayload {
                     channel,
-                    client_id,
+                    client_id: &client_id,
                     data,
-                })?;
+                    id: id.clone(),
+                    ext,
+                };
+
+                Ok((id, self.send_retryable(&payload)?))
+            }
+            None => Err(Error::new(
+                ErrorKind::NotHandshaked,
+                "No client id set for publish",
+            )),
+        }
+    }
+}
+
+/// An iterator over the messages received by a [Client](Client), returned by
+/// [messages](Client::messages).
+pub struct Messages<'a> {
+    client: &'a mut Client,
+    pending: VecDeque<Response>,
+    polled: bool,
+    failed: bool,
+}
+
+impl Iterator for Messages<'_> {
+    type Item = Result<Response, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(resp) = self.pending.pop_front() {
+                return Some(Ok(resp));
+            }
+            if self.failed || self.client.client_id.is_none() {
+                return None;
+            }
+            if self.polled {
+                self.client
+                    .wait_for_interval(self.client.advice.as_ref(), None);
+            }
+            self.polled = true;
+            match self.client.poll_messages() {
+                Ok(resps) => self.pending.extend(resps),
+                Err(err) => {
+                    self.failed = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
 
-                self.handle_response(resp)
+impl Drop for Client {
+    fn drop(&mut self) {
+        if self.auto_disconnect && self.client_id.is_some() {
+            log::debug!("Disconnecting the dropped cometd client");
+            if let Err(err) = self.disconnect() {
+                log::warn!("Could not disconnect the dropped cometd client: {}", err);
             }
-            None => Err(Error::new("No client id set for unsubscribe")),
         }
     }
 }
@@ -0,0 +1,470 @@
+use reqwest::{Client as ReqwestClient, Response as ReqwestReponse, Url};
+use serde::Serialize;
+use std::time::Duration;
+
+use super::{
+    advised_step, check_id, merge_cookies, parse_body, prepare_resend, recovery_advice,
+    status_error, ConnectPayload, DisconnectPayload, HandshakePayload, ParsedBody, PublishPayload,
+    RawResponse, Step, SubscribeTopicPayload,
+};
+use crate::advice::Advice;
+use crate::auth::AuthScheme;
+use crate::channel::{self, validate_channel, ChannelKind};
+use crate::config::{COMETD_SUPPORTED_TYPES, COMETD_USER_AGENT, COMETD_VERSION};
+use crate::error::{Error, ErrorKind};
+use crate::response::Response;
+use crate::transport::Transport;
+
+/// Reads the cookies and the body of a response from the server.
+async fn read_response(resp: ReqwestReponse) -> Result<RawResponse, Error> {
+    let status = resp.status();
+    let cookies = resp
+        .cookies()
+        .map(|c| format!("{}={}", c.name(), c.value()))
+        .collect::<Vec<_>>();
+    let body = resp.text().await?;
+
+    if !status.is_success() {
+        return Err(status_error(status, &body, true));
+    }
+    Ok(RawResponse { body, cookies })
+}
+
+/// The asynchronous cometd client. It never blocks the calling thread while waiting
+/// for the server, which makes it usable from within an async runtime.
+///
+/// It follows the advice of the server the same way as the blocking [Client](crate::Client),
+/// sending again the request the server advised to retry, but it is a reduced client:
+///
+/// * it only uses long-polling, without `minimumVersion` nor `ext` in its messages;
+/// * it does not wait for the advised interval, nor use the advised timeout;
+/// * it does not resubscribe after following a `handshake` advice;
+/// * it does not expose the received messages as a `Stream`.
+pub struct AsyncClient {
+    http_client: ReqwestClient,
+    base_url: Url,
+    access_token: String,
+    auth: AuthScheme,
+    client_id: Option<String>,
+    cookies: Vec<String>,
+    message_id: u64,
+    max_retries: i8,
+    actual_retries: i8,
+    version: String,
+    rehandshake_on_unknown_client: bool,
+    retried_request: Option<serde_json::Value>,
+}
+
+impl AsyncClient {
+    /// Creates a new asynchronous cometd client. It is expected to provide the url of the
+    /// cometd server, the access token to allow the communication and the timeout for
+    /// long-polling requests.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if the http client cannot be initalized.
+    pub fn new(
+        base_url: &str,
+        access_token: &str,
+        timeout: Duration,
+    ) -> Result<AsyncClient, Error> {
+        let url = Url::parse(base_url)
+            .map_err(|err| Error::with_source(ErrorKind::Parse, "Could not parse base url", err))?;
+        let http_client = ReqwestClient::builder()
+            .cookie_store(true)
+            .user_agent(COMETD_USER_AGENT)
+            .redirect(reqwest::redirect::Policy::none())
+            .timeout(timeout)
+            .build()
+            .map_err(|err| {
+                Error::with_source(
+                    ErrorKind::Transport,
+                    "Could not initialize http client",
+                    err,
+                )
+            })?;
+
+        log::info!("Successfully created async cometd client");
+        Ok(AsyncClient {
+            http_client,
+            base_url: url,
+            access_token: access_token.to_owned(),
+            auth: AuthScheme::OAuth,
+            client_id: None,
+            cookies: vec![],
+            message_id: 0,
+            actual_retries: 0,
+            max_retries: 1,
+            version: COMETD_VERSION.to_owned(),
+            rehandshake_on_unknown_client: false,
+            retried_request: None,
+        })
+    }
+
+    /// Sets the number of retries the client will attempt in case of an error or a retry advice is
+    /// returned by the cometd server. `0` disables them, see
+    /// [Client::set_retries](crate::Client::set_retries).
+    pub fn set_retries(mut self, retries: i8) -> Self {
+        self.max_retries = retries;
+        self
+    }
+
+    /// Sets the Bayeux protocol version sent during the handshake, see
+    /// [Client::set_version](crate::Client::set_version).
+    pub fn set_version(mut self, version: &str) -> Self {
+        self.version = version.to_owned();
+        self
+    }
+
+    /// Sets whether the client handshakes again when the server no longer knows it, see
+    /// [Client::set_auto_rehandshake_on_unknown_client](crate::Client::set_auto_rehandshake_on_unknown_client).
+    pub fn set_auto_rehandshake_on_unknown_client(mut self, rehandshake: bool) -> Self {
+        self.rehandshake_on_unknown_client = rehandshake;
+        self
+    }
+
+    /// Returns the client id negotiated with the server during the handshake, or `None`
+    /// if no handshake has been made yet.
+    pub fn client_id(&self) -> Option<&str> {
+        self.client_id.as_deref()
+    }
+
+    /// Returns the id of the last message sent to the server.
+    pub fn message_id(&self) -> u64 {
+        self.message_id
+    }
+
+    fn next_message_id(&mut self) -> String {
+        self.message_id += 1;
+        self.message_id.to_string()
+    }
+
+    /// Sets the authentication scheme used to build the authentication header of
+    /// every request. Defaults to [`AuthScheme::OAuth`](AuthScheme::OAuth).
+    pub fn set_auth(mut self, auth: AuthScheme) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    async fn send_request(&self, body: &(impl Serialize + Sync)) -> Result<ReqwestReponse, Error> {
+        let mut req = self
+            .http_client
+            .post(self.base_url.clone())
+            .header(reqwest::header::ACCEPT, "application/json")
+            .json(body);
+
+        req = match self.auth {
+            AuthScheme::OAuth => req.header(
+                reqwest::header::AUTHORIZATION,
+                format!("OAuth {}", self.access_token),
+            ),
+            AuthScheme::Bearer => req.bearer_auth(&self.access_token),
+            AuthScheme::Basic { ref user, ref pass } => req.basic_auth(user, Some(pass)),
+            AuthScheme::Custom {
+                ref header_name,
+                ref value,
+            } => req.header(header_name.as_str(), value.as_str()),
+            AuthScheme::None => req,
+        };
+        if !self.cookies.is_empty() {
+            req = req.header(reqwest::header::COOKIE, self.cookies.join("; "));
+        }
+
+        log::debug!(
+            "Sending request to cometd with the following body: {:?}",
+            serde_json::to_string(body)
+        );
+        Ok(req.send().await?)
+    }
+
+    async fn send_connect(&mut self) -> Result<ReqwestReponse, Error> {
+        match self.client_id.clone() {
+            Some(client_id) => {
+                let id = self.next_message_id();
+
+                self.send_request(&ConnectPayload {
+                    channel: channel::CONNECT,
+                    client_id: &client_id,
+                    connection_type: Transport::LongPolling.connection_type(),
+                    id,
+                    ext: None,
+                })
+                .await
+            }
+            None => Err(Error::new(
+                ErrorKind::NotHandshaked,
+                "No client id set for connect",
+            )),
+        }
+    }
+
+    async fn send_handshake(&mut self) -> Result<ReqwestReponse, Error> {
+        let id = self.next_message_id();
+
+        self.send_request(&HandshakePayload {
+            channel: channel::HANDSHAKE,
+            version: &self.version,
+            minimum_version: None,
+            supported_connection_types: COMETD_SUPPORTED_TYPES.to_vec(),
+            id,
+            ext: None,
+        })
+        .await
+    }
+
+    /// Sends a request which is sent again rather than a connect when the server advises to
+    /// retry, and handles its response.
+    async fn send_retryable(
+        &mut self,
+        body: &(impl Serialize + Sync),
+    ) -> Result<Vec<Response>, Error> {
+        let body = serde_json::to_value(body)?;
+        let resp = self.send_request(&body).await?;
+        let previous = self.retried_request.replace(body);
+        let resps = self.handle_response(resp, false).await;
+
+        self.retried_request = previous;
+        resps
+    }
+
+    /// Checks that the advice from the server can be followed. Returns the request to send next.
+    fn follow_advice(&mut self, advice: &Advice, error: Option<&str>) -> Result<Step, Error> {
+        log::debug!("Following advice from server");
+        let step = advised_step(advice, error)?;
+
+        if self.actual_retries < self.max_retries {
+            self.actual_retries += 1;
+            log::debug!("Retry n°{}", self.actual_retries);
+            Ok(step)
+        } else {
+            Err(Error::new(
+                ErrorKind::MaxRetriesReached,
+                error.unwrap_or("Max retries reached"),
+            ))
+        }
+    }
+
+    /// Handles the messages of a single response body. Returns the successful messages along
+    /// with the request to send next if the server advised to reconnect or handshake.
+    fn process_response(
+        &mut self,
+        resp: RawResponse,
+    ) -> Result<(Vec<Response>, Option<Step>), Error> {
+        let RawResponse { body, cookies } = resp;
+        let expected_id = self.message_id.to_string();
+        let mut responses = vec![];
+        let mut next = None;
+
+        merge_cookies(&mut self.cookies, cookies);
+        match parse_body(&body, true)? {
+            ParsedBody::Errored(resps) => {
+                for resp in resps.into_iter() {
+                    check_id(&resp.channel, resp.id.as_deref(), &expected_id)?;
+                    if next.is_none() {
+                        let advice = recovery_advice(
+                            &resp,
+                            &self.version,
+                            self.rehandshake_on_unknown_client,
+                        )?;
+
+                        next = Some(self.follow_advice(&advice, Some(&resp.error))?);
+                    }
+                }
+            }
+            ParsedBody::Successful(resps) => {
+                for resp in resps.into_iter() {
+                    check_id(resp.channel(), resp.id(), &expected_id)?;
+                    if let Some(ref advice) = resp.advice() {
+                        if resp.is_successful() == Some(false) {
+                            if next.is_none() {
+                                next = Some(self.follow_advice(advice, None)?);
+                            }
+                            continue;
+                        }
+                    }
+                    if let Response::Handshake(ref resp) = resp {
+                        self.client_id = Some(resp.client_id.clone());
+                    }
+                    responses.push(resp);
+                }
+            }
+        }
+        Ok((responses, next))
+    }
+
+    /// Handles a response from the server, following its advice in a loop until it stops
+    /// advising to reconnect or the retries are exhausted, as the blocking client does. When the
+    /// response is the one of a `handshake`, the advice is followed by handshaking again and the
+    /// responses of the successful handshake are returned.
+    async fn handle_response(
+        &mut self,
+        resp: ReqwestReponse,
+        handshake: bool,
+    ) -> Result<Vec<Response>, Error> {
+        let mut resp = resp;
+        let mut responses = vec![];
+        // The responses to a handshake sent to follow an advice are not returned, only the ones
+        // of the request sent after it.
+        let mut handshaking = false;
+
+        loop {
+            let (resps, next) = self.process_response(read_response(resp).await?)?;
+            if !handshaking || handshake {
+                responses.extend(resps);
+            }
+
+            let next = match next {
+                Some(_) if handshake => Step::Handshake,
+                Some(next) => next,
+                None if handshake => return Ok(responses),
+                None if handshaking => {
+                    handshaking = false;
+                    Step::Connect
+                }
+                None => return Ok(responses),
+            };
+            resp = match next {
+                Step::Connect => match self.retried_request.clone() {
+                    Some(mut body) => {
+                        let client_id = self.client_id.clone();
+
+                        prepare_resend(&mut body, client_id.as_deref(), || self.next_message_id());
+                        self.send_request(&body).await?
+                    }
+                    None => self.send_connect().await?,
+                },
+                Step::Handshake => {
+                    handshaking = true;
+                    self.send_handshake().await?
+                }
+            };
+        }
+    }
+
+    async fn handshake(&mut self) -> Result<Vec<Response>, Error> {
+        let resps = match self.send_handshake().await {
+            Ok(resp) => self.handle_response(resp, true).await,
+            Err(err) => Err(err),
+        };
+
+        self.actual_retries = 0;
+        resps
+    }
+
+    /// The cometd connect method. See [Client::connect](crate::Client::connect).
+    pub async fn connect(&mut self) -> Result<Vec<Response>, Error> {
+        let resps = match self.send_connect().await {
+            Ok(resp) => self.handle_response(resp, false).await,
+            Err(err) => Err(err),
+        };
+
+        self.actual_retries = 0;
+        resps
+    }
+
+    /// The cometd disconnect method. See [Client::disconnect](crate::Client::disconnect).
+    pub async fn disconnect(&mut self) -> Result<Vec<Response>, Error> {
+        match self.client_id.clone() {
+            Some(client_id) => {
+                let id = self.next_message_id();
+                let resp = self
+                    .send_request(&DisconnectPayload {
+                        channel: channel::DISCONNECT,
+                        client_id: &client_id,
+                        id,
+                    })
+                    .await?;
+                let resps = self.handle_response(resp, false).await?;
+
+                self.client_id = None;
+                self.cookies.clear();
+                Ok(resps)
+            }
+            None => Err(Error::new(
+                ErrorKind::NotHandshaked,
+                "No client id set for disconnect",
+            )),
+        }
+    }
+
+    /// Init the cometd client. See [Client::init](crate::Client::init).
+    pub async fn init(&mut self) -> Result<Vec<Response>, Error> {
+        let resps = self.handshake().await?;
+
+        log::info!("Successfully init async cometd client");
+        Ok(resps)
+    }
+
+    /// The cometd subscribe method. See [Client::subscribe](crate::Client::subscribe).
+    pub async fn subscribe(&mut self, subscription: &str) -> Result<Vec<Response>, Error> {
+        validate_channel(subscription, ChannelKind::Subscribe)?;
+        match self.client_id.clone() {
+            Some(client_id) => {
+                let id = self.next_message_id();
+
+                self.send_retryable(&SubscribeTopicPayload {
+                    channel: channel::SUBSCRIBE,
+                    client_id: &client_id,
+                    subscription,
+                    id,
+                    ext: None,
+                })
+                .await
+            }
+            None => Err(Error::new(
+                ErrorKind::NotHandshaked,
+                "No client id set for subscribe",
+            )),
+        }
+    }
+
+    /// The cometd unsubscribe method. See [Client::unsubscribe](crate::Client::unsubscribe).
+    pub async fn unsubscribe(&mut self, subscription: &str) -> Result<Vec<Response>, Error> {
+        validate_channel(subscription, ChannelKind::Subscribe)?;
+        match self.client_id.clone() {
+            Some(client_id) => {
+                let id = self.next_message_id();
+
+                self.send_retryable(&SubscribeTopicPayload {
+                    channel: channel::UNSUBSCRIBE,
+                    client_id: &client_id,
+                    subscription,
+                    id,
+                    ext: None,
+                })
+                .await
+            }
+            None => Err(Error::new(
+                ErrorKind::NotHandshaked,
+                "No client id set for unsubscribe",
+            )),
+        }
+    }
+
+    /// The cometd publish method. See [Client::publish](crate::Client::publish).
+    pub async fn publish(
+        &mut self,
+        channel: &str,
+        data: impl Serialize + Sync,
+    ) -> Result<Vec<Response>, Error> {
+        validate_channel(channel, ChannelKind::Publish)?;
+        match self.client_id.clone() {
+            Some(client_id) => {
+                let id = self.next_message_id();
+
+                self.send_retryable(&PublishPayload {
+                    channel,
+                    client_id: &client_id,
+                    data,
+                    id,
+                    ext: None,
+                })
+                .await
+            }
+            None => Err(Error::new(
+                ErrorKind::NotHandshaked,
+                "No client id set for publish",
+            )),
+        }
+    }
+}
@@ -0,0 +1,447 @@
+use reqwest::blocking::Client as ReqwestClient;
+use reqwest::header::HeaderMap;
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, Identity, Proxy, Url};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::Client;
+use crate::auth::AuthScheme;
+use crate::config::{
+    COMETD_DEFAULT_TIMEOUT, COMETD_MAX_NETWORK_DELAY, COMETD_USER_AGENT, COMETD_VERSION,
+};
+use crate::error::{Error, ErrorKind};
+use crate::metrics::NoMetrics;
+use crate::state::ClientState;
+use crate::transport::Transport;
+
+/// Builds a [Client](Client) step by step. Only the base url is mandatory,
+/// every other option has a default value.
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    base_url: Option<String>,
+    access_token: String,
+    timeout: Option<Duration>,
+    transport_retries: i8,
+    advice_reconnects: i8,
+    auth: AuthScheme,
+    transport: Transport,
+    cookie_store: bool,
+    accept_invalid_certs: bool,
+    require_https: bool,
+    root_certificates: Vec<Certificate>,
+    identity: Option<Identity>,
+    proxies: Vec<Proxy>,
+    proxy_url: Option<String>,
+    http2_prior_knowledge: bool,
+    pool_max_idle_per_host: Option<usize>,
+    tcp_keepalive: Option<Duration>,
+    #[cfg(feature = "gzip")]
+    gzip: bool,
+    #[cfg(feature = "deflate")]
+    deflate: bool,
+    http_client: Option<ReqwestClient>,
+    user_agent: String,
+    redirect: Arc<Policy>,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        ClientBuilder {
+            base_url: None,
+            access_token: String::new(),
+            timeout: Some(COMETD_DEFAULT_TIMEOUT),
+            transport_retries: 1,
+            advice_reconnects: 1,
+            auth: AuthScheme::OAuth,
+            transport: Transport::LongPolling,
+            cookie_store: true,
+            accept_invalid_certs: false,
+            require_https: false,
+            root_certificates: vec![],
+            identity: None,
+            proxies: vec![],
+            proxy_url: None,
+            http2_prior_knowledge: false,
+            pool_max_idle_per_host: None,
+            tcp_keepalive: None,
+            #[cfg(feature = "gzip")]
+            gzip: true,
+            #[cfg(feature = "deflate")]
+            deflate: true,
+            http_client: None,
+            user_agent: COMETD_USER_AGENT.to_owned(),
+            redirect: Arc::new(Policy::none()),
+        }
+    }
+}
+
+impl ClientBuilder {
+    /// Creates a builder with the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the url of the cometd server.
+    pub fn base_url(mut self, base_url: &str) -> Self {
+        self.base_url = Some(base_url.to_owned());
+        self
+    }
+
+    /// Sets the access token used by the authentication scheme.
+    pub fn access_token(mut self, access_token: &str) -> Self {
+        self.access_token = access_token.to_owned();
+        self
+    }
+
+    /// Sets the timeout for long-polling requests.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Disables the timeout of the requests, which then wait for the server as long as needed,
+    /// for setups relying on the server to close the connection.
+    pub fn no_timeout(mut self) -> Self {
+        self.timeout = None;
+        self
+    }
+
+    /// Sets the number of retries the client will attempt in case of an error or a retry
+    /// advice is returned by the cometd server, in addition to the first attempt. `0`
+    /// disables them.
+    pub fn retries(mut self, retries: i8) -> Self {
+        self.transport_retries = retries;
+        self.advice_reconnects = retries;
+        self
+    }
+
+    /// Sets the number of times a connect or handshake request is sent again when it could
+    /// not reach the server.
+    pub fn transport_retries(mut self, retries: i8) -> Self {
+        self.transport_retries = retries;
+        self
+    }
+
+    /// Sets the number of times the client follows a reconnect advice from the server
+    /// before giving up.
+    pub fn advice_reconnects(mut self, reconnects: i8) -> Self {
+        self.advice_reconnects = reconnects;
+        self
+    }
+
+    /// Sets the authentication scheme. Defaults to [`AuthScheme::OAuth`](AuthScheme::OAuth).
+    pub fn auth(mut self, auth: AuthScheme) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Sets the transport. Defaults to [`Transport::LongPolling`](Transport::LongPolling).
+    /// [`Transport::WebSocket`](Transport::WebSocket) requires the `websocket` feature, and
+    /// does not support the TLS and proxy options: the root certificates, the identity,
+    /// accepting invalid certificates and the proxies.
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Sets whether the http client stores the cookies it receives and sends them back.
+    /// Defaults to `true`. The client keeps the cookies of the session itself, see
+    /// [Client::cookies](Client::cookies), so they are sent back either way.
+    pub fn cookie_store(mut self, cookie_store: bool) -> Self {
+        self.cookie_store = cookie_store;
+        self
+    }
+
+    /// Disables the validation of the server's certificate, which allows self-signed
+    /// certificates. This is dangerous and should only be used in controlled environments.
+    pub fn danger_accept_invalid_certs(mut self, accept_invalid_certs: bool) -> Self {
+        self.accept_invalid_certs = accept_invalid_certs;
+        self
+    }
+
+    /// Sets whether building the client fails if the url of the server does not use https,
+    /// so that the credentials are never sent in clear. Otherwise, only a warning is logged.
+    /// Neither applies when no credentials are configured. The client keeps enforcing it when
+    /// credentials are added later on and when failing over to the hosts advised by the server.
+    pub fn require_https(mut self, require_https: bool) -> Self {
+        self.require_https = require_https;
+        self
+    }
+
+    /// Adds a certificate trusted to validate the server's certificate, such as the one of an
+    /// internal certificate authority.
+    pub fn add_root_certificate(mut self, certificate: Certificate) -> Self {
+        self.root_certificates.push(certificate);
+        self
+    }
+
+    /// Sets the certificate the client presents to the server, for mutual TLS.
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Adds a proxy the requests go through. If none is set, the system proxies are picked up
+    /// from the environment, as reqwest does by default.
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxies.push(proxy);
+        self
+    }
+
+    /// Sends every request through the proxy at the given url. See [proxy](ClientBuilder::proxy).
+    pub fn proxy_url(mut self, proxy_url: &str) -> Self {
+        self.proxy_url = Some(proxy_url.to_owned());
+        self
+    }
+
+    /// Sets the `User-Agent` header sent along with every request. Defaults to
+    /// [COMETD_USER_AGENT](crate::config::COMETD_USER_AGENT).
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_owned();
+        self
+    }
+
+    /// Sets how the redirects answered by the server are followed. Defaults to
+    /// [`Policy::none`](Policy::none), so that the credentials are never sent to an
+    /// unexpected host: a redirect is then returned as an error carrying its status.
+    pub fn redirect(mut self, policy: Policy) -> Self {
+        self.redirect = Arc::new(policy);
+        self
+    }
+
+    /// Sets whether requests are sent with HTTP/2 right away, without negotiating it first.
+    /// The server must support HTTP/2. Defaults to `false`.
+    pub fn http2_prior_knowledge(mut self, http2_prior_knowledge: bool) -> Self {
+        self.http2_prior_knowledge = http2_prior_knowledge;
+        self
+    }
+
+    /// Sets the maximum number of idle connections kept open to the server for later requests.
+    /// Defaults to reqwest's default, which does not limit them.
+    pub fn pool_max_idle_per_host(mut self, max: usize) -> Self {
+        self.pool_max_idle_per_host = Some(max);
+        self
+    }
+
+    /// Sets the interval of the TCP keepalive probes sent on the connections to the server.
+    /// They are not sent by default.
+    pub fn tcp_keepalive(mut self, interval: Duration) -> Self {
+        self.tcp_keepalive = Some(interval);
+        self
+    }
+
+    /// Sets whether gzip compressed responses are accepted and decompressed. Defaults to
+    /// `true`.
+    #[cfg(feature = "gzip")]
+    pub fn gzip(mut self, gzip: bool) -> Self {
+        self.gzip = gzip;
+        self
+    }
+
+    /// Sets whether deflate compressed responses are accepted and decompressed. Defaults to
+    /// `true`.
+    #[cfg(feature = "deflate")]
+    pub fn deflate(mut self, deflate: bool) -> Self {
+        self.deflate = deflate;
+        self
+    }
+
+    /// Sets the http client used to send the requests, for instance to share its connection
+    /// pool. The options configuring the http client, such as the TLS and proxy ones, are then
+    /// ignored, except for the timeout which is set on every request.
+    pub fn http_client(mut self, http_client: ReqwestClient) -> Self {
+        self.http_client = Some(http_client);
+        self
+    }
+
+    /// Returns the first option configured which the WebSocket transport does not support,
+    /// if any.
+    fn websocket_unsupported_option(&self) -> Option<&'static str> {
+        if !self.root_certificates.is_empty() {
+            Some("add_root_certificate")
+        } else if self.identity.is_some() {
+            Some("identity")
+        } else if self.accept_invalid_certs {
+            Some("danger_accept_invalid_certs")
+        } else if !self.proxies.is_empty() || self.proxy_url.is_some() {
+            Some("proxy")
+        } else {
+            None
+        }
+    }
+
+    /// Builds the http client according to the options of the builder.
+    fn build_http_client(&self) -> Result<ReqwestClient, Error> {
+        // The policy is shared so that the builder stays cloneable.
+        let redirect = self.redirect.clone();
+        let mut http_client = ReqwestClient::builder()
+            .redirect(Policy::custom(move |attempt| redirect.redirect(attempt)))
+            .cookie_store(self.cookie_store)
+            .danger_accept_invalid_certs(self.accept_invalid_certs)
+            .user_agent(self.user_agent.as_str())
+            .timeout(self.timeout);
+
+        if self.http2_prior_knowledge {
+            http_client = http_client.http2_prior_knowledge();
+        }
+        if let Some(max) = self.pool_max_idle_per_host {
+            http_client = http_client.pool_max_idle_per_host(max);
+        }
+        if let Some(interval) = self.tcp_keepalive {
+            http_client = http_client.tcp_keepalive(interval);
+        }
+        #[cfg(feature = "gzip")]
+        {
+            http_client = http_client.gzip(self.gzip);
+        }
+        #[cfg(feature = "deflate")]
+        {
+            http_client = http_client.deflate(self.deflate);
+        }
+        for certificate in &self.root_certificates {
+            http_client = http_client.add_root_certificate(certificate.clone());
+        }
+        if let Some(ref identity) = self.identity {
+            http_client = http_client.identity(identity.clone());
+        }
+        if let Some(ref proxy_url) = self.proxy_url {
+            let proxy = Proxy::all(proxy_url).map_err(|err| {
+                Error::with_source(ErrorKind::Parse, "Could not parse proxy url", err)
+            })?;
+
+            http_client = http_client.proxy(proxy);
+        }
+        for proxy in &self.proxies {
+            http_client = http_client.proxy(proxy.clone());
+        }
+        http_client.build().map_err(|err| {
+            Error::with_source(
+                ErrorKind::Transport,
+                "Could not initialize http client",
+                err,
+            )
+        })
+    }
+
+    /// Builds the cometd client.
+    ///
+    /// # Errors
+    ///
+    /// Will return an error if no base url was provided, if it or the proxy url cannot be parsed,
+    /// if it does not use https while [required](ClientBuilder::require_https) and credentials
+    /// are configured, if the transport is not enabled or does not support one of the options,
+    /// or if the http client cannot be initalized.
+    pub fn build(self) -> Result<Client, Error> {
+        let base_url = self
+            .base_url
+            .as_deref()
+            .ok_or_else(|| Error::new(ErrorKind::Parse, "No base url provided"))?;
+        let url = Url::parse(base_url)
+            .map_err(|err| Error::with_source(ErrorKind::Parse, "Could not parse base url", err))?;
+        if url.scheme() != "https" && self.auth.has_credentials(&self.access_token) {
+            if self.require_https {
+                return Err(Error::new(
+                    ErrorKind::Parse,
+                    &format!(
+                        "The base url must use https to send credentials, not {}",
+                        url.scheme()
+                    ),
+                ));
+            }
+            log::warn!(
+                "The base url uses {} rather than https, the credentials are sent in clear",
+                url.scheme()
+            );
+        }
+        self.transport.check_enabled()?;
+        let websocket_unsupported_option = self.websocket_unsupported_option();
+        if let (Transport::WebSocket, Some(option)) = (self.transport, websocket_unsupported_option)
+        {
+            let message = format!(
+                "The websocket transport does not support the {} option",
+                option
+            );
+
+            return Err(Error::new(ErrorKind::Transport, &message));
+        }
+        let http_client = match self.http_client {
+            Some(ref http_client) => http_client.clone(),
+            None => self.build_http_client()?,
+        };
+
+        log::info!("Successfully created cometd client");
+        Ok(Client {
+            http_client,
+            base_url: url,
+            access_token: self.access_token,
+            auth: self.auth,
+            require_https: self.require_https,
+            client_id: None,
+            state: ClientState::Unconnected,
+            cookies: vec![],
+            headers: HeaderMap::new(),
+            timeout: self.timeout,
+            timeout_override: None,
+            message_id: 0,
+            handshake_ext: None,
+            replay: BTreeMap::new(),
+            endpoints: HashMap::new(),
+            subscriptions: HashSet::new(),
+            pending_subscriptions: vec![],
+            version: COMETD_VERSION.to_owned(),
+            minimum_version: None,
+            advice: None,
+            retried_request: None,
+            auto_disconnect: true,
+            rehandshake_on_unknown_client: false,
+            follow_advice_hosts: false,
+            log_bodies: true,
+            strict_parsing: false,
+            partial_results: false,
+            partial_errors: vec![],
+            pending_messages: VecDeque::new(),
+            max_messages_per_poll: None,
+            buffer_size: 0,
+            recent_messages: VecDeque::new(),
+            treat_timeout_as_empty: false,
+            transport: self.transport,
+            connection_type: None,
+            supported_connection_types: vec![],
+            negotiated_connection_type: None,
+            server_supported_types: vec![],
+            #[cfg(feature = "websocket")]
+            websocket: Mutex::new(None),
+            #[cfg(feature = "websocket")]
+            websocket_unrelated: Mutex::new(vec![]),
+            #[cfg(feature = "websocket")]
+            websocket_unsupported_option,
+            max_transport_retries: self.transport_retries,
+            transport_retries: 0,
+            max_advice_reconnects: self.advice_reconnects,
+            advice_reconnects: 0,
+            retries_started: Instant::now(),
+            max_reconnect_duration: None,
+            backoff: None,
+            default_interval: Duration::ZERO,
+            max_network_delay: COMETD_MAX_NETWORK_DELAY,
+            on_reconnect: None,
+            on_handshake: None,
+            idle_timeout: None,
+            on_idle: None,
+            serializer: None,
+            ext_provider: None,
+            timesync: false,
+            ack_extension: false,
+            ack_id: None,
+            clock_offset: None,
+            network_lag: None,
+            cancelled: None,
+            session: None,
+            dry_run: false,
+            dry_run_requests: Mutex::new(vec![]),
+            metrics: Arc::new(NoMetrics),
+        })
+    }
+}
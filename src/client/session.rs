@@ -0,0 +1,137 @@
+use reqwest::blocking::Client as ReqwestClient;
+use reqwest::header::{HeaderMap, ACCEPT, CONTENT_TYPE};
+use reqwest::Url;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use super::{parse_body, read_http_response, ParsedBody, PublishPayload};
+use crate::channel::{validate_channel, ChannelKind};
+use crate::error::{Error, ErrorKind};
+use crate::response::Response;
+
+/// The part of the client state a [Session](Session) needs to publish, kept up to date by
+/// the client.
+#[derive(Debug)]
+pub(crate) struct SessionState {
+    pub(crate) client_id: Option<String>,
+    pub(crate) base_url: Url,
+    pub(crate) headers: HeaderMap,
+}
+
+/// The state shared between a client and its sessions.
+#[derive(Debug)]
+pub(crate) struct SharedSession {
+    pub(crate) state: RwLock<SessionState>,
+    message_id: AtomicU64,
+}
+
+impl SharedSession {
+    pub(crate) fn new(state: SessionState) -> SharedSession {
+        SharedSession {
+            state: RwLock::new(state),
+            message_id: AtomicU64::new(0),
+        }
+    }
+}
+
+/// A handle on the session of a [Client](super::Client) allowing to publish from several
+/// threads at once, while the client itself keeps long-polling the server. Obtained through
+/// [session](super::Client::session), it follows the handshakes and disconnections of the
+/// client.
+///
+/// A session only shares the client id, the base url and the headers of the client, so it
+/// publishes differently from [Client::publish](super::Client::publish):
+///
+/// * it does not follow the advice of the server: a failed publish is returned as an error
+///   right away;
+/// * it always posts over long-polling to the base url, whatever the
+///   [transport](super::Client::set_transport) and the
+///   [endpoint paths](super::Client::set_endpoint_path) of the client;
+/// * the messages are serialized with `serde_json`, not the
+///   [serializer](super::Client::set_serializer) of the client;
+/// * no `ext` is added by the [ext provider](super::Client::set_ext_provider);
+/// * [partial results](super::Client::set_partial_results) are not supported, and only the
+///   error of the first rejected message of a batch is returned.
+#[derive(Debug, Clone)]
+pub struct Session {
+    http_client: ReqwestClient,
+    timeout: Option<Duration>,
+    log_bodies: bool,
+    shared: Arc<SharedSession>,
+}
+
+impl Session {
+    pub(crate) fn new(
+        http_client: ReqwestClient,
+        timeout: Option<Duration>,
+        log_bodies: bool,
+        shared: Arc<SharedSession>,
+    ) -> Session {
+        Session {
+            http_client,
+            timeout,
+            log_bodies,
+            shared,
+        }
+    }
+
+    /// Publishes a message to a channel. The messages published through a session carry
+    /// their own ids, prefixed with `s`, so they never collide with the ones of the client.
+    ///
+    /// # Errors
+    ///
+    /// The channel name is invalid.
+    /// The client has not been initialized or was disconnected.
+    /// The cometd server could not be reached or its response could not be parsed.
+    /// The cometd server returned a response that indicated an error.
+    pub fn publish(&self, channel: &str, data: impl Serialize) -> Result<Vec<Response>, Error> {
+        validate_channel(channel, ChannelKind::Publish)?;
+        let id = format!(
+            "s{}",
+            self.shared.message_id.fetch_add(1, Ordering::SeqCst) + 1
+        );
+        let (base_url, headers, body) = {
+            let state = self
+                .shared
+                .state
+                .read()
+                .map_err(|_| Error::new(ErrorKind::Transport, "Session is poisoned"))?;
+            let client_id = state.client_id.as_deref().ok_or_else(|| {
+                Error::new(ErrorKind::NotHandshaked, "No client id set for publish")
+            })?;
+            let body = serde_json::to_string(&PublishPayload {
+                channel,
+                client_id,
+                data,
+                id,
+                ext: None,
+            })?;
+
+            (state.base_url.clone(), state.headers.clone(), body)
+        };
+        let mut req = self
+            .http_client
+            .post(base_url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(ACCEPT, "application/json")
+            .headers(headers)
+            .body(body);
+
+        if let Some(timeout) = self.timeout {
+            req = req.timeout(timeout);
+        }
+        let resp = read_http_response(req.send()?, self.log_bodies)?;
+
+        match parse_body(&resp.body, self.log_bodies)? {
+            ParsedBody::Errored(resps) => Err(Error::new(
+                ErrorKind::Protocol,
+                resps
+                    .first()
+                    .map_or("The server rejected the message", |resp| &resp.error),
+            )),
+            ParsedBody::Successful(resps) => Ok(resps),
+        }
+    }
+}
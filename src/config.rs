@@ -1,4 +1,13 @@
+use std::time::Duration;
+
 /// The version of the cometd protocol to use
-pub static COMETD_VERSION: &'static str = "1.0";
+pub static COMETD_VERSION: &str = "1.0";
 /// The supported connection types
-pub static COMETD_SUPPORTED_TYPES: [&'static str; 1] = ["long-polling"];
+pub static COMETD_SUPPORTED_TYPES: [&str; 1] = ["long-polling"];
+/// The timeout for long-polling requests used when none is provided
+pub static COMETD_DEFAULT_TIMEOUT: Duration = Duration::from_secs(120);
+/// The time allowed on top of the timeout advised by the server for a connect request to
+/// travel back and forth
+pub static COMETD_MAX_NETWORK_DELAY: Duration = Duration::from_secs(10);
+/// The user agent sent along with the requests when none is provided
+pub static COMETD_USER_AGENT: &str = concat!("cometd-rs/", env!("CARGO_PKG_VERSION"));
@@ -1,15 +1,129 @@
+use std::fmt;
+
+/// The category of an [Error](Error), allowing to programmatically branch on it. New kinds
+/// may be added, so matches on it need a wildcard arm.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The request could not reach the server or its response could not be read.
+    Transport,
+    /// The server's response or a provided value could not be parsed.
+    Parse,
+    /// The server answered with an error.
+    Protocol,
+    /// The request kept failing after the maximum number of retries.
+    MaxRetriesReached,
+    /// The request requires a handshake which has not been made yet.
+    NotHandshaked,
+    /// The server advised not to reconnect nor handshake.
+    ServerAdvisedStop,
+    /// The server does not support the version of the protocol requested by the client.
+    VersionNegotiation,
+    /// The request was cancelled through a [CancelHandle](crate::client::CancelHandle).
+    Cancelled,
+}
+
 /// Represents an error. Every time an error is created through
 /// the [`new`](Error::new) function. It will log an error.
 #[derive(Debug)]
 pub struct Error {
+    kind: ErrorKind,
     pub message: String,
+    /// The underlying error that caused this one, if any.
+    pub source: Option<Box<dyn std::error::Error + Send + Sync>>,
+    /// The raw body sent by the server, when it could not be parsed.
+    pub body: Option<String>,
+    /// The HTTP status of the response, when the server answered with an error status.
+    pub status: Option<u16>,
 }
 
 impl Error {
-    pub fn new(msg: &str) -> Error {
+    pub fn new(kind: ErrorKind, msg: &str) -> Error {
         log::error!("{}", msg);
         Error {
+            kind,
+            message: msg.to_owned(),
+            source: None,
+            body: None,
+            status: None,
+        }
+    }
+
+    /// Creates an error caused by another one. The cause can later be retrieved
+    /// through [`source`](std::error::Error::source) and downcasted to its original type.
+    pub fn with_source(
+        kind: ErrorKind,
+        msg: &str,
+        source: impl std::error::Error + Send + Sync + 'static,
+    ) -> Error {
+        log::error!("{}: {}", msg, source);
+        Error {
+            kind,
             message: msg.to_owned(),
+            source: Some(Box::new(source)),
+            body: None,
+            status: None,
         }
     }
+
+    /// Attaches the raw body of the response that caused the error.
+    pub(crate) fn with_body(mut self, body: &str) -> Error {
+        self.body = Some(body.to_owned());
+        self
+    }
+
+    /// Attaches the HTTP status of the response that caused the error.
+    pub(crate) fn with_status(mut self, status: u16) -> Error {
+        self.status = Some(status);
+        self
+    }
+
+    /// Returns whether the error comes from a request that did not complete before its timeout.
+    pub(crate) fn is_timeout(&self) -> bool {
+        self.source
+            .as_ref()
+            .and_then(|source| source.downcast_ref::<reqwest::Error>())
+            .is_some_and(reqwest::Error::is_timeout)
+    }
+
+    /// Returns the category of the error.
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_ref()
+            .map(|source| source.as_ref() as &(dyn std::error::Error + 'static))
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Error {
+        let (kind, msg) = if err.is_builder() {
+            (ErrorKind::Parse, "Could not build request")
+        } else if err.is_timeout() {
+            (ErrorKind::Transport, "Request to server timed out")
+        } else if err.is_body() || err.is_decode() {
+            (ErrorKind::Transport, "Could not get the response body")
+        } else {
+            (ErrorKind::Transport, "Could not send request to server")
+        };
+
+        Error::with_source(kind, msg, err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        Error::with_source(ErrorKind::Parse, "Could not process JSON", err)
+    }
 }
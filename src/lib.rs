@@ -1,12 +1,24 @@
 pub mod advice;
+pub mod auth;
+pub mod channel;
 pub mod client;
 pub mod config;
 pub mod error;
+pub mod metrics;
 pub mod response;
+pub mod state;
 #[cfg(test)]
 mod tests;
+pub mod transport;
 
 pub use advice::Advice;
-pub use client::Client;
-pub use error::Error;
+pub use auth::AuthScheme;
+pub use channel::ChannelKind;
+#[cfg(feature = "async")]
+pub use client::async_client::AsyncClient;
+pub use client::{CancelHandle, Client, ClientBuilder, Session};
+pub use error::{Error, ErrorKind};
+pub use metrics::Metrics;
 pub use response::Response;
+pub use state::ClientState;
+pub use transport::Transport;
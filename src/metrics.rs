@@ -0,0 +1,41 @@
+use crate::error::ErrorKind;
+
+/// The counter incremented for every request sent to the server.
+pub const REQUESTS: &str = "requests";
+/// The counter incremented every time the client follows a reconnect or handshake advice.
+pub const RETRIES: &str = "retries";
+/// The counter incremented every time a request is sent again because it could not reach
+/// the server.
+pub const TRANSPORT_RETRIES: &str = "transport_retries";
+/// The counter incremented for every successful handshake.
+pub const HANDSHAKES: &str = "handshakes";
+
+/// Receives the counters of the client activity, to forward them to a metrics system such
+/// as Prometheus or statsd.
+pub trait Metrics: Send + Sync {
+    /// Increments the counter with the given name. Errors are counted under
+    /// `errors.{kind}`, such as `errors.transport`.
+    fn incr(&self, name: &str);
+}
+
+/// A [Metrics](Metrics) implementation discarding every counter. This is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoMetrics;
+
+impl Metrics for NoMetrics {
+    fn incr(&self, _name: &str) {}
+}
+
+/// Returns the name of the counter incremented for an error of the given kind.
+pub(crate) fn error_counter(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::Transport => "errors.transport",
+        ErrorKind::Parse => "errors.parse",
+        ErrorKind::Protocol => "errors.protocol",
+        ErrorKind::MaxRetriesReached => "errors.max_retries_reached",
+        ErrorKind::NotHandshaked => "errors.not_handshaked",
+        ErrorKind::ServerAdvisedStop => "errors.server_advised_stop",
+        ErrorKind::VersionNegotiation => "errors.version_negotiation",
+        ErrorKind::Cancelled => "errors.cancelled",
+    }
+}
@@ -1,10 +1,101 @@
+use serde::de::{DeserializeOwned, IgnoredAny};
 use serde::Deserialize;
+use std::fmt;
 
 use crate::advice::Advice;
+use crate::error::{Error, ErrorKind};
+
+/// Deserializes the data of a message into `T`.
+fn data_as<T: DeserializeOwned>(data: &serde_json::Value) -> Result<T, Error> {
+    serde_json::from_value(data.clone()).map_err(|err| {
+        Error::with_source(ErrorKind::Parse, "Could not deserialize message data", err)
+    })
+}
+
+/// Deserializes the ext of a message into `T`, if there is one.
+fn ext_as<T: DeserializeOwned>(ext: Option<&serde_json::Value>) -> Option<Result<T, Error>> {
+    ext.map(|ext| {
+        serde_json::from_value(ext.clone()).map_err(|err| {
+            Error::with_source(ErrorKind::Parse, "Could not deserialize message ext", err)
+        })
+    })
+}
+
+/// Mirrors the fields of every response, rejecting the ones none of them expect. Used to
+/// surface the fields that the regular parsing silently ignores.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+#[allow(dead_code)]
+struct StrictMessage {
+    channel: IgnoredAny,
+    successful: Option<IgnoredAny>,
+    error: Option<IgnoredAny>,
+    version: Option<IgnoredAny>,
+    minimum_version: Option<IgnoredAny>,
+    client_id: Option<IgnoredAny>,
+    supported_connection_types: Option<IgnoredAny>,
+    subscription: Option<IgnoredAny>,
+    advice: Option<StrictAdvice>,
+    ext: Option<IgnoredAny>,
+    data: Option<IgnoredAny>,
+    id: Option<IgnoredAny>,
+    auth_successful: Option<IgnoredAny>,
+}
+
+/// Mirrors the fields of an [Advice](Advice), rejecting the unknown ones.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+#[allow(dead_code)]
+struct StrictAdvice {
+    reconnect: Option<IgnoredAny>,
+    timeout: Option<IgnoredAny>,
+    interval: Option<IgnoredAny>,
+    #[serde(rename = "multiple-clients")]
+    multiple_clients: Option<IgnoredAny>,
+    hosts: Option<IgnoredAny>,
+}
+
+/// Checks that the messages of a body, be it an array or a single message, only carry the
+/// fields the responses expect.
+///
+/// # Errors
+///
+/// The body is not valid JSON or one of its messages carries an unknown field.
+pub(crate) fn check_known_fields(body: &str) -> Result<(), Error> {
+    let strict_error = |err| {
+        Error::with_source(
+            ErrorKind::Parse,
+            &format!("Strict parsing rejected the response: {}", err),
+            err,
+        )
+    };
+
+    match serde_json::from_str::<serde_json::Value>(body)? {
+        serde_json::Value::Array(messages) => {
+            for message in messages {
+                serde_json::from_value::<StrictMessage>(message).map_err(strict_error)?;
+            }
+        }
+        message => {
+            serde_json::from_value::<StrictMessage>(message).map_err(strict_error)?;
+        }
+    }
+    Ok(())
+}
+
+/// The channel or channels a subscribe or unsubscribe response relates to.
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+#[serde(untagged)]
+pub enum Subscription {
+    /// A single channel.
+    One(String),
+    /// Several channels, when they were subscribed to through a single message.
+    Many(Vec<String>),
+}
 
 /// This response is the basic reponse for any that does not match the other
 /// field of this enum.
-#[derive(Deserialize, PartialEq, Debug)]
+#[derive(Deserialize, PartialEq, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct BasicResponse {
     pub channel: String,
@@ -13,9 +104,22 @@ pub struct BasicResponse {
     pub advice: Option<Advice>,
     pub ext: Option<serde_json::Value>,
     pub client_id: Option<String>,
+    pub subscription: Option<Subscription>,
     pub id: Option<String>,
 }
 
+impl BasicResponse {
+    /// Deserializes the ext of the message into the given type, or returns `None` if the
+    /// message has no ext.
+    ///
+    /// # Errors
+    ///
+    /// The ext does not match the given type.
+    pub fn ext_as<T: DeserializeOwned>(&self) -> Option<Result<T, Error>> {
+        ext_as(self.ext.as_ref())
+    }
+}
+
 /// This response is returned upon a successful handshake request.
 #[derive(Deserialize, PartialEq, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -32,6 +136,18 @@ pub struct HandshakeResponse {
     pub auth_successful: Option<bool>,
 }
 
+impl HandshakeResponse {
+    /// Deserializes the ext of the message into the given type, or returns `None` if the
+    /// message has no ext.
+    ///
+    /// # Errors
+    ///
+    /// The ext does not match the given type.
+    pub fn ext_as<T: DeserializeOwned>(&self) -> Option<Result<T, Error>> {
+        ext_as(self.ext.as_ref())
+    }
+}
+
 /// Represents an errored response from the cometd server. If an advice is provided,
 /// the client might automatically retry the request.
 #[derive(Deserialize, PartialEq, Clone, Debug)]
@@ -40,13 +156,56 @@ pub struct ErroredResponse {
     pub channel: String,
     pub successful: bool,
     pub error: String,
+    pub version: Option<String>,
+    pub minimum_version: Option<String>,
     pub client_id: Option<String>,
-    pub subscription: Option<String>,
+    pub subscription: Option<Subscription>,
     pub advice: Option<Advice>,
     pub ext: Option<serde_json::Value>,
     pub id: Option<String>,
 }
 
+impl ErroredResponse {
+    /// Deserializes the ext of the message into the given type, or returns `None` if the
+    /// message has no ext.
+    ///
+    /// # Errors
+    ///
+    /// The ext does not match the given type.
+    pub fn ext_as<T: DeserializeOwned>(&self) -> Option<Result<T, Error>> {
+        ext_as(self.ext.as_ref())
+    }
+
+    /// Splits the error into its code, arguments and message, following the Bayeux
+    /// `code:args:message` format. Servers such as Salesforce separate them with `::`
+    /// instead, which is supported as well. If the error does not follow this format,
+    /// no code nor arguments are returned and the message is the whole error.
+    pub fn error_parts(&self) -> (Option<u16>, Vec<String>, String) {
+        let parts = if self.error.contains("::") {
+            self.error.splitn(3, "::").collect::<Vec<_>>()
+        } else {
+            self.error.splitn(3, ':').collect::<Vec<_>>()
+        };
+        let (code, args, message) = match parts.as_slice() {
+            [code, message] => (code, "", message),
+            [code, args, message] => (code, *args, message),
+            _ => return (None, vec![], self.error.clone()),
+        };
+
+        match code.trim().parse() {
+            Ok(code) => (
+                Some(code),
+                args.split(',')
+                    .filter(|arg| !arg.is_empty())
+                    .map(ToOwned::to_owned)
+                    .collect(),
+                (*message).to_owned(),
+            ),
+            Err(_) => (None, vec![], self.error.clone()),
+        }
+    }
+}
+
 /// This response is returned upon a successful publish request.
 #[derive(Deserialize, PartialEq, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -61,6 +220,27 @@ pub struct PublishResponse {
     pub id: Option<String>,
 }
 
+impl PublishResponse {
+    /// Deserializes the data of the message into the given type.
+    ///
+    /// # Errors
+    ///
+    /// The data does not match the given type.
+    pub fn data_as<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        data_as(&self.data)
+    }
+
+    /// Deserializes the ext of the message into the given type, or returns `None` if the
+    /// message has no ext.
+    ///
+    /// # Errors
+    ///
+    /// The ext does not match the given type.
+    pub fn ext_as<T: DeserializeOwned>(&self) -> Option<Result<T, Error>> {
+        ext_as(self.ext.as_ref())
+    }
+}
+
 /// This response is returned when a message is send to a channel the client
 /// is subscribed to.
 #[derive(Deserialize, PartialEq, Clone, Debug)]
@@ -73,8 +253,29 @@ pub struct DeliveryResponse {
     pub id: Option<String>,
 }
 
+impl DeliveryResponse {
+    /// Deserializes the data of the message into the given type.
+    ///
+    /// # Errors
+    ///
+    /// The data does not match the given type.
+    pub fn data_as<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        data_as(&self.data)
+    }
+
+    /// Deserializes the ext of the message into the given type, or returns `None` if the
+    /// message has no ext.
+    ///
+    /// # Errors
+    ///
+    /// The ext does not match the given type.
+    pub fn ext_as<T: DeserializeOwned>(&self) -> Option<Result<T, Error>> {
+        ext_as(self.ext.as_ref())
+    }
+}
+
 /// Represents a response from the cometd server.
-#[derive(Deserialize, PartialEq, Debug)]
+#[derive(Deserialize, PartialEq, Clone, Debug)]
 #[serde(untagged)]
 pub enum Response {
     /// This response is returned upon a successful handshake request.
@@ -90,6 +291,64 @@ pub enum Response {
 }
 
 impl Response {
+    /// Returns the channel the response was sent on.
+    pub fn channel(&self) -> &str {
+        match self {
+            Response::Handshake(resp) => &resp.channel,
+            Response::Publish(resp) => &resp.channel,
+            Response::Delivery(resp) => &resp.channel,
+            Response::Basic(resp) => &resp.channel,
+        }
+    }
+
+    /// Returns whether the response was sent on `channel`. Since a delivery on a channel
+    /// matches it as well, the acknowledgement of a request is the matching response for
+    /// which [is_successful](Response::is_successful) is not `None`.
+    pub fn matches_channel(&self, channel: &str) -> bool {
+        self.channel() == channel
+    }
+
+    /// Returns whether the server reported the request as successful, or `None` for
+    /// deliveries since they do not carry this information.
+    pub fn is_successful(&self) -> Option<bool> {
+        match self {
+            Response::Handshake(resp) => Some(resp.successful),
+            Response::Publish(resp) => Some(resp.successful),
+            Response::Delivery(_) => None,
+            Response::Basic(resp) => Some(resp.successful),
+        }
+    }
+
+    /// Returns the id of the message if the server provided one.
+    pub fn id(&self) -> Option<&str> {
+        match self {
+            Response::Handshake(resp) => resp.id.as_deref(),
+            Response::Publish(resp) => resp.id.as_deref(),
+            Response::Delivery(resp) => resp.id.as_deref(),
+            Response::Basic(resp) => resp.id.as_deref(),
+        }
+    }
+
+    /// Returns the ext of the message if the server provided one.
+    pub fn ext(&self) -> Option<&serde_json::Value> {
+        match self {
+            Response::Handshake(resp) => resp.ext.as_ref(),
+            Response::Publish(resp) => resp.ext.as_ref(),
+            Response::Delivery(resp) => resp.ext.as_ref(),
+            Response::Basic(resp) => resp.ext.as_ref(),
+        }
+    }
+
+    /// Deserializes the ext of the message into the given type, or returns `None` if the
+    /// message has no ext.
+    ///
+    /// # Errors
+    ///
+    /// The ext does not match the given type.
+    pub fn ext_as<T: DeserializeOwned>(&self) -> Option<Result<T, Error>> {
+        ext_as(self.ext())
+    }
+
     /// Returns an [Advice](Advice) if the server returned one.
     pub fn advice(&self) -> Option<Advice> {
         match self {
@@ -100,3 +359,23 @@ impl Response {
         }
     }
 }
+
+impl fmt::Display for Response {
+    /// Prints a concise summary of the response: its kind, channel and whether it was
+    /// successful, if known.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Response::Handshake(_) => "Handshake",
+            Response::Publish(_) => "Publish",
+            Response::Delivery(_) => "Delivery",
+            Response::Basic(_) => "Basic",
+        };
+
+        write!(f, "{} on {}", kind, self.channel())?;
+        match self.is_successful() {
+            Some(true) => write!(f, " (successful)"),
+            Some(false) => write!(f, " (unsuccessful)"),
+            None => Ok(()),
+        }
+    }
+}
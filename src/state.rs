@@ -0,0 +1,13 @@
+/// The state of the session between the client and the cometd server.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum ClientState {
+    /// No handshake has been made yet. This is the initial state.
+    #[default]
+    Unconnected,
+    /// The handshake succeeded but no connect request has succeeded since.
+    Handshaked,
+    /// The last connect request succeeded.
+    Connected,
+    /// The session has been closed, either by the client or by the server.
+    Disconnected,
+}
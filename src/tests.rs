@@ -1,14 +1,18 @@
-use mockito::mock;
-use std::time::Duration;
+use mockito::{mock, Matcher};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
 
+use crate::auth::AuthScheme;
 use crate::client::Client;
+use crate::error::ErrorKind;
 
-static VALID_ACCESS_TOKEN: &'static str = "1234";
+static VALID_ACCESS_TOKEN: &str = "1234";
 static RETRIES_MAX: i8 = 3;
 
 fn client() -> Client {
     Client::new(
-        &format!("{}", mockito::server_url()),
+        &mockito::server_url(),
         VALID_ACCESS_TOKEN,
         Duration::from_secs(120),
     )
@@ -16,6 +20,31 @@ fn client() -> Client {
     .set_retries(RETRIES_MAX)
 }
 
+static HANDSHAKE_RESPONSE: &str = "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]";
+
+/// Reads a whole HTTP request carrying a JSON body from a raw connection.
+fn read_http_request(stream: &mut TcpStream) -> String {
+    let mut request = vec![];
+    let mut buf = [0; 1024];
+
+    while !request.ends_with(b"}") {
+        let read = stream.read(&mut buf).expect("Could not read request");
+        request.extend_from_slice(&buf[..read]);
+    }
+    String::from_utf8_lossy(&request).into_owned()
+}
+
+/// Writes an HTTP response with the given body to a raw connection.
+fn write_http_response(stream: &mut TcpStream, body: &str) {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )
+    .expect("Could not write response");
+}
+
 mod init {
     use super::*;
 
@@ -30,6 +59,19 @@ mod init {
         assert!(client.init().is_err());
     }
 
+    #[test]
+    fn reports_unsupported_version() {
+        let _m = mock("POST", "/")
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/handshake\",\"error\":\"406::Unsupported version, or unsupported minimum version\",\"successful\":false,\"version\":\"2.0\",\"minimumVersion\":\"2.0\"}]")
+            .create();
+        let mut client = client();
+        let err = client.init().expect_err("Init should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::VersionNegotiation);
+        assert!(err.message.contains("at least version 2.0"));
+    }
+
     #[test]
     fn works() {
         let _m = mock("POST", "/")
@@ -42,67 +84,3899 @@ mod init {
 
         assert!(client.init().is_ok());
     }
-}
-
-mod connect {
-    use super::*;
 
     #[test]
-    fn retries_if_server_advises_to() {
-        let _m = mock("POST", "/")
-            .match_body(
-                "{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"supportedConnectionTypes\":[\"long-polling\"]}"
+    fn sends_handshake_ext() {
+        let m = mock("POST", "/")
+            .match_body(Matcher::Json(serde_json::json!({
+                "channel": "/meta/handshake",
+                "version": "1.0",
+                "supportedConnectionTypes": ["long-polling"],
+                "id": "1",
+                "ext": {"authentication": {"token": "1234"}},
+            })))
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
             )
+            .create();
+        let mut client =
+            client().set_handshake_ext(serde_json::json!({"authentication": {"token": "1234"}}));
+
+        client.init().expect("Could not init client");
+        m.assert();
+    }
+
+    #[test]
+    fn sends_minimum_version() {
+        let m = mock("POST", "/")
+            .match_body(Matcher::Json(serde_json::json!({
+                "channel": "/meta/handshake",
+                "version": "1.0",
+                "minimumVersion": "1.0",
+                "supportedConnectionTypes": ["long-polling"],
+                "id": "1",
+            })))
             .with_status(200)
             .with_body(
                 "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
             )
             .create();
+        let mut client = client().set_minimum_version("1.0");
+
+        client.init().expect("Could not init client");
+        m.assert();
+    }
+
+    #[test]
+    fn returns_handshake_response() {
+        let _m = mock("POST", "/")
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let mut client = client();
+        let resp = client.init_handshake().expect("Could not init client");
+
+        assert_eq!(resp.client_id, "1234");
+        assert_eq!(resp.supported_connection_types, vec!["long-polling"]);
+    }
+
+    #[test]
+    fn returns_handshake_response_after_handshake_advice() {
+        let _first = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\",\"id\":\"1\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"advice\":{\"reconnect\":\"handshake\",\"interval\":0},\"channel\":\"/meta/handshake\",\"error\":\"403::Unknown client\",\"successful\":false}]")
+            .create();
+        let _second = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\",\"id\":\"2\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
         let connect_mock = mock("POST", "/")
-            .match_body(
-                "{\"channel\":\"/meta/connect\",\"clientId\":\"1234\",\"connectionType\":\"long-polling\"}"
-            )
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .expect(0)
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+        let resp = client.init_handshake().expect("Could not init client");
+
+        assert_eq!(resp.client_id, "1234");
+        connect_mock.assert();
+    }
+
+    #[test]
+    fn errors_without_handshake_response() {
+        let _m = mock("POST", "/")
             .with_status(200)
-            .with_body("[{\"advice\":{\"reconnect\":\"retry\"},\"channel\":\"/meta/connect\",\"error\":\"400::Error\",\"successful\":false}]")
-            .expect(RETRIES_MAX as usize + 1)
+            .with_body("[{\"channel\":\"/meta/handshake\",\"successful\":true}]")
+            .create();
+        let mut client = client();
+        let err = client
+            .init_handshake()
+            .expect_err("Init should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Protocol);
+    }
+
+    #[test]
+    fn sends_custom_version() {
+        let m = mock("POST", "/")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "channel": "/meta/handshake",
+                "version": "0.9",
+            })))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let mut client = client().set_version("0.9");
+
+        client.init().expect("Could not init client");
+        m.assert();
+    }
+
+    #[test]
+    fn accepts_bare_object_response() {
+        let _m = mock("POST", "/")
+            .with_status(200)
+            .with_body(
+                "{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}",
+            )
             .create();
         let mut client = client();
 
         client.init().expect("Could not init client");
-        client.connect().expect_err("Connect should not return Ok");
+        assert_eq!(client.client_id(), Some("1234"));
+    }
+
+    #[test]
+    fn accepts_bare_object_error() {
+        let _m = mock("POST", "/")
+            .with_status(200)
+            .with_body("{\"channel\":\"/meta/handshake\",\"successful\":false,\"error\":\"403::Forbidden\"}")
+            .create();
+        let mut client = client();
+        let err = client.init().expect_err("Init should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Protocol);
+        assert_eq!(err.message, "403::Forbidden");
+    }
+
+    #[test]
+    fn starts_with_handshake_and_connect() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/connect\",\"successful\":true}]")
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+        let resps = client.start().expect("Could not start client");
+
+        assert_eq!(resps.len(), 2);
         connect_mock.assert();
     }
 
     #[test]
-    fn handshake_if_advises_to() {
-        let hs_mock = mock("POST", "/")
-            .match_body(
-                "{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"supportedConnectionTypes\":[\"long-polling\"]}"
-            )
+    fn does_not_connect_if_handshake_fails() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/handshake\",\"successful\":false,\"error\":\"403::Forbidden\"}]")
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .expect(0)
+            .create();
+        let mut client = client();
+
+        client.start().expect_err("Start should not return Ok");
+        connect_mock.assert();
+    }
+
+    #[test]
+    fn checks_supported_connection_types() {
+        let _m = mock("POST", "/")
             .with_status(200)
             .with_body(
-                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"websocket\"]}]",
             )
-            .expect(RETRIES_MAX as usize) // Will do : Handshake + Connect + Retry HS (1) + Connect (2) + Retry HS (3)
             .create();
+        let mut client = client();
+        let err = client.init().expect_err("Init should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Protocol);
+        assert_eq!(
+            err.message,
+            "The server does not support the long-polling connection type, only websocket"
+        );
+        assert_eq!(client.client_id(), None);
+    }
+
+    #[test]
+    fn stores_server_supported_types() {
         let _m = mock("POST", "/")
-            .match_body(
-                "{\"channel\":\"/meta/connect\",\"clientId\":\"1234\",\"connectionType\":\"long-polling\"}"
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"websocket\",\"long-polling\"]}]",
             )
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+
+        assert!(client.server_supported_types().is_empty());
+        client.init().expect("Could not init client");
+        assert_eq!(
+            client.server_supported_types(),
+            ["websocket".to_owned(), "long-polling".to_owned()]
+        );
+    }
+
+    #[test]
+    fn stores_client_id() {
+        let _m = mock("POST", "/")
             .with_status(200)
             .with_body(
-                "[{\"advice\":{\"reconnect\":\"handshake\"},\"channel\":\"/meta/connect\",\"successful\":false,\"error\":\"error\"}]",
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
             )
             .create();
         let mut client = client();
 
+        assert_eq!(client.client_id(), None);
         client.init().expect("Could not init client");
-        let resp = client.connect().expect_err("Connect should not return Ok");
-        println!("{:#?}", resp);
-        hs_mock.assert();
+        assert_eq!(client.client_id(), Some("1234"));
+    }
+}
+
+mod builder {
+    use super::*;
+    use crate::client::ClientBuilder;
+
+    #[test]
+    fn rejects_http_when_https_required() {
+        let err = ClientBuilder::new()
+            .base_url(&mockito::server_url())
+            .access_token("1234")
+            .require_https(true)
+            .build()
+            .err()
+            .expect("Build should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Parse);
+    }
+
+    #[test]
+    fn keeps_requiring_https_after_build() {
+        let builder = ClientBuilder::new()
+            .base_url(&mockito::server_url())
+            .auth(AuthScheme::None)
+            .require_https(true);
+        let client = builder
+            .clone()
+            .build()
+            .expect("Could not build cometd client");
+        let err = client
+            .set_auth(AuthScheme::Basic {
+                user: "user".to_owned(),
+                pass: "pass".to_owned(),
+            })
+            .err()
+            .expect("Auth should be rejected");
+
+        assert_eq!(err.kind(), ErrorKind::Parse);
+        let client = builder.build().expect("Could not build cometd client");
+        let err = client
+            .set_header("X-Api-Key", "secret")
+            .err()
+            .expect("Header should be rejected");
+
+        assert_eq!(err.kind(), ErrorKind::Parse);
+    }
+
+    #[test]
+    fn accepts_http_without_credentials() {
+        ClientBuilder::new()
+            .base_url(&mockito::server_url())
+            .auth(AuthScheme::None)
+            .access_token("1234")
+            .require_https(true)
+            .build()
+            .expect("Could not build cometd client");
+        ClientBuilder::new()
+            .base_url(&mockito::server_url())
+            .require_https(true)
+            .build()
+            .expect("Could not build cometd client");
+    }
+
+    #[cfg(not(feature = "websocket"))]
+    #[test]
+    fn rejects_disabled_websocket_transport() {
+        use crate::transport::Transport;
+
+        let err = ClientBuilder::new()
+            .base_url(&mockito::server_url())
+            .transport(Transport::WebSocket)
+            .build()
+            .err()
+            .expect("Build should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Transport);
+    }
+
+    #[test]
+    fn rejects_websocket_with_unsupported_options() {
+        use crate::transport::Transport;
+
+        let err = ClientBuilder::new()
+            .base_url(&mockito::server_url())
+            .transport(Transport::WebSocket)
+            .danger_accept_invalid_certs(true)
+            .build()
+            .err()
+            .expect("Build should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Transport);
+    }
+
+    #[cfg(feature = "websocket")]
+    #[test]
+    fn rejects_websocket_set_after_build_with_unsupported_options() {
+        use crate::transport::Transport;
+
+        let mut client = ClientBuilder::new()
+            .base_url(&mockito::server_url())
+            .proxy_url("http://127.0.0.1:1")
+            .build()
+            .expect("Could not build cometd client")
+            .set_transport(Transport::WebSocket);
+        let err = client.init().expect_err("Init should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Transport);
+        assert!(err.message.contains("proxy"));
+    }
+
+    #[test]
+    fn accepts_http_unless_https_required() {
+        ClientBuilder::new()
+            .base_url(&mockito::server_url())
+            .require_https(false)
+            .build()
+            .expect("Could not build cometd client");
+    }
+
+    #[test]
+    fn builds_configured_client() {
+        let m = mock("POST", "/")
+            .match_header("authorization", "Bearer 1234")
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
+            )
+            .create();
+        let mut client = ClientBuilder::new()
+            .base_url(&mockito::server_url())
+            .access_token(VALID_ACCESS_TOKEN)
+            .timeout(Duration::from_secs(30))
+            .retries(RETRIES_MAX)
+            .auth(AuthScheme::Bearer)
+            .build()
+            .expect("Could not build cometd client");
+
+        assert_eq!(client.current_timeout(), Duration::from_secs(30));
+        client.init().expect("Could not init client");
+        m.assert();
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn decompresses_gzip_responses() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder
+            .write_all(HANDSHAKE_RESPONSE.as_bytes())
+            .expect("Could not compress response");
+        let m = mock("POST", "/")
+            .match_header("accept-encoding", Matcher::Regex("gzip".to_owned()))
+            .with_status(200)
+            .with_header("content-encoding", "gzip")
+            .with_body(encoder.finish().expect("Could not compress response"))
+            .create();
+        let mut client = ClientBuilder::new()
+            .base_url(&mockito::server_url())
+            .gzip(true)
+            .build()
+            .expect("Could not build cometd client");
+
+        client.init().expect("Could not init client");
+        assert_eq!(client.client_id(), Some("1234"));
+        m.assert();
+    }
+
+    #[test]
+    fn does_not_follow_redirects_by_default() {
+        let redirect_mock = mock("POST", "/")
+            .with_status(302)
+            .with_header("location", &format!("{}/moved", mockito::server_url()))
+            .create();
+        let moved_mock = mock("GET", "/moved")
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .expect(0)
+            .create();
+        let mut client = ClientBuilder::new()
+            .base_url(&mockito::server_url())
+            .build()
+            .expect("Could not build cometd client");
+        let err = client.init().expect_err("Init should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Protocol);
+        assert!(err.message.contains("302"));
+        redirect_mock.assert();
+        moved_mock.assert();
+    }
+
+    #[test]
+    fn follows_redirects_when_configured() {
+        let _redirect_mock = mock("POST", "/")
+            .with_status(302)
+            .with_header("location", &format!("{}/moved", mockito::server_url()))
+            .create();
+        let moved_mock = mock("GET", "/moved")
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let mut client = ClientBuilder::new()
+            .base_url(&mockito::server_url())
+            .redirect(reqwest::redirect::Policy::limited(10))
+            .build()
+            .expect("Could not build cometd client");
+
+        client.init().expect("Could not init client");
+        moved_mock.assert();
+    }
+
+    #[test]
+    fn builds_with_tls_options() {
+        let client = Client::builder()
+            .base_url("https://127.0.0.1")
+            .cookie_store(false)
+            .danger_accept_invalid_certs(true)
+            .build();
+
+        assert!(client.is_ok());
+    }
+
+    #[test]
+    fn builds_with_connection_options() {
+        let client = Client::builder()
+            .base_url("https://127.0.0.1")
+            .http2_prior_knowledge(true)
+            .pool_max_idle_per_host(4)
+            .tcp_keepalive(Duration::from_secs(30))
+            .build();
+
+        assert!(client.is_ok());
+    }
+
+    #[test]
+    fn sends_user_agent() {
+        let default_mock = mock("POST", "/")
+            .match_header(
+                "user-agent",
+                concat!("cometd-rs/", env!("CARGO_PKG_VERSION")),
+            )
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+
+        client.init().expect("Could not init client");
+        default_mock.assert();
+
+        let custom_mock = mock("POST", "/")
+            .match_header("user-agent", "my-app/1.0")
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let mut client = Client::builder()
+            .base_url(&mockito::server_url())
+            .user_agent("my-app/1.0")
+            .build()
+            .expect("Could not build cometd client")
+            .set_auto_disconnect(false);
+
+        client.init().expect("Could not init client");
+        custom_mock.assert();
+    }
+
+    #[test]
+    fn uses_provided_http_client() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("x-shared", reqwest::header::HeaderValue::from_static("yes"));
+        let http_client = reqwest::blocking::Client::builder()
+            .default_headers(headers)
+            .build()
+            .expect("Could not build http client");
+        let m = mock("POST", "/")
+            .match_header("x-shared", "yes")
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let mut client =
+            Client::with_http_client(http_client, &mockito::server_url(), VALID_ACCESS_TOKEN)
+                .expect("Could not build cometd client")
+                .set_auto_disconnect(false);
+
+        client.init().expect("Could not init client");
+        m.assert();
+    }
+
+    #[test]
+    fn builds_without_timeout() {
+        let client = Client::builder()
+            .base_url(&mockito::server_url())
+            .no_timeout()
+            .build()
+            .expect("Could not build cometd client");
+
+        assert_eq!(client.current_timeout(), Duration::MAX);
+    }
+
+    #[test]
+    fn sends_requests_through_proxy() {
+        let m = mock("POST", Matcher::Any)
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let mut client = Client::builder()
+            .base_url("http://cometd.invalid")
+            .proxy_url(&mockito::server_url())
+            .build()
+            .expect("Could not build cometd client")
+            .set_auto_disconnect(false);
+
+        client.init().expect("Could not init client");
+        m.assert();
+    }
+
+    #[test]
+    fn rejects_invalid_proxy_url() {
+        let client = Client::builder()
+            .base_url("http://127.0.0.1")
+            .proxy_url("not a url")
+            .build();
+
+        assert_eq!(client.err().map(|err| err.kind()), Some(ErrorKind::Parse));
+    }
+
+    #[test]
+    fn requires_base_url() {
+        let err = ClientBuilder::new()
+            .access_token(VALID_ACCESS_TOKEN)
+            .build()
+            .err()
+            .expect("Build should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Parse);
     }
 }
 
-mod subscribe {}
-mod unsubscribe {}
-mod publish {}
+mod auth {
+    use super::*;
+
+    fn handshake_with_header(header: &str, value: Matcher) -> mockito::Mock {
+        mock("POST", "/")
+            .match_header(header, value)
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
+            )
+            .create()
+    }
+
+    #[test]
+    fn defaults_to_oauth() {
+        let m = handshake_with_header("authorization", Matcher::Exact("OAuth 1234".to_owned()));
+        let mut client = client();
+
+        client.init().expect("Could not init client");
+        m.assert();
+    }
+
+    #[test]
+    fn sends_bearer() {
+        let m = handshake_with_header("authorization", Matcher::Exact("Bearer 1234".to_owned()));
+        let mut client = client()
+            .set_auth(AuthScheme::Bearer)
+            .expect("Could not set auth");
+
+        client.init().expect("Could not init client");
+        m.assert();
+    }
+
+    #[test]
+    fn sends_basic() {
+        let m = handshake_with_header(
+            "authorization",
+            Matcher::Exact("Basic dXNlcjpwYXNz".to_owned()),
+        );
+        let mut client = client()
+            .set_auth(AuthScheme::Basic {
+                user: "user".to_owned(),
+                pass: "pass".to_owned(),
+            })
+            .expect("Could not set auth");
+
+        client.init().expect("Could not init client");
+        m.assert();
+    }
+
+    #[test]
+    fn sends_custom_header() {
+        let m = handshake_with_header("x-api-key", Matcher::Exact("secret".to_owned()));
+        let mut client = client()
+            .set_auth(AuthScheme::Custom {
+                header_name: "X-Api-Key".to_owned(),
+                value: "secret".to_owned(),
+            })
+            .expect("Could not set auth");
+
+        client.init().expect("Could not init client");
+        m.assert();
+    }
+
+    #[test]
+    fn sends_nothing_for_none() {
+        let m = handshake_with_header("authorization", Matcher::Missing);
+        let mut client = client()
+            .set_auth(AuthScheme::None)
+            .expect("Could not set auth");
+
+        client.init().expect("Could not init client");
+        m.assert();
+    }
+
+    #[test]
+    fn sends_nothing_when_unauthenticated() {
+        let m = handshake_with_header("authorization", Matcher::Missing);
+        let mut client =
+            Client::new_unauthenticated(&mockito::server_url(), Duration::from_secs(120))
+                .expect("Could not build cometd client");
+
+        client.init().expect("Could not init client");
+        m.assert();
+    }
+}
+
+mod connect {
+    use super::*;
+
+    #[test]
+    fn keeps_last_advice() {
+        use crate::advice::Reconnect;
+
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/connect\",\"successful\":true,\"advice\":{\"reconnect\":\"retry\",\"interval\":250,\"timeout\":30000}}]")
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+
+        client.init().expect("Could not init client");
+        assert_eq!(client.last_advice(), None);
+        client.connect().expect("Could not connect");
+        let advice = client.last_advice().expect("Advice should be kept");
+
+        assert_eq!(advice.reconnect, Reconnect::Retry);
+        assert_eq!(advice.interval, Some(250));
+        assert_eq!(advice.timeout, Some(30000));
+    }
+
+    #[test]
+    fn retries_if_server_advises_to() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"supportedConnectionTypes\":[\"long-polling\"]}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
+            )
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\",\"clientId\":\"1234\",\"connectionType\":\"long-polling\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"advice\":{\"reconnect\":\"retry\"},\"channel\":\"/meta/connect\",\"error\":\"400::Error\",\"successful\":false}]")
+            .expect(RETRIES_MAX as usize + 1)
+            .create();
+        let mut client = client();
+
+        client.init().expect("Could not init client");
+        let err = client.connect().expect_err("Connect should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::MaxRetriesReached);
+        connect_mock.assert();
+    }
+
+    #[test]
+    fn follows_advice_independently_from_transport_retries() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
+            )
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"advice\":{\"reconnect\":\"retry\"},\"channel\":\"/meta/connect\",\"error\":\"400::Error\",\"successful\":false}]")
+            .expect(6)
+            .create();
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .set_transport_retries(0)
+            .set_advice_reconnects(5);
+
+        client.init().expect("Could not init client");
+        let err = client.connect().expect_err("Connect should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::MaxRetriesReached);
+        connect_mock.assert();
+    }
+
+    #[test]
+    fn stops_retrying_after_max_reconnect_duration() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"advice\":{\"reconnect\":\"retry\",\"interval\":100},\"channel\":\"/meta/connect\",\"error\":\"400::Error\",\"successful\":false}]")
+            .create();
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .set_retries(100)
+            .set_max_reconnect_duration(Duration::from_millis(300));
+
+        client.init().expect("Could not init client");
+        let start = Instant::now();
+        let err = client.connect().expect_err("Connect should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::MaxRetriesReached);
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(client.retries_used() < 10);
+    }
+
+    #[test]
+    fn cuts_waits_to_max_reconnect_duration() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"advice\":{\"reconnect\":\"retry\",\"interval\":120000},\"channel\":\"/meta/connect\",\"error\":\"400::Error\",\"successful\":false}]")
+            .create();
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .set_max_reconnect_duration(Duration::from_millis(300));
+
+        client.init().expect("Could not init client");
+        let start = Instant::now();
+        let err = client.connect().expect_err("Connect should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::MaxRetriesReached);
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn stops_transport_retries_after_max_reconnect_duration() {
+        let mut client = Client::new("http://127.0.0.1:1", "1234", Duration::from_secs(1))
+            .expect("Could not build cometd client")
+            .set_auto_disconnect(false)
+            .set_transport_retries(100)
+            .set_backoff(Duration::from_secs(60), Duration::from_secs(60))
+            .set_max_reconnect_duration(Duration::from_millis(300));
+        let start = Instant::now();
+        let err = client.init().expect_err("Init should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Transport);
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(client.transport_retries_used(), 1);
+    }
+
+    #[test]
+    fn sends_retries_plus_one_requests() {
+        for retries in 0..3 {
+            let _m = mock("POST", "/")
+                .match_body(Matcher::PartialJsonString(
+                    "{\"channel\":\"/meta/handshake\"}".to_owned(),
+                ))
+                .with_status(200)
+                .with_body(HANDSHAKE_RESPONSE)
+                .create();
+            let connect_mock = mock("POST", "/")
+                .match_body(Matcher::PartialJsonString(
+                    "{\"channel\":\"/meta/connect\"}".to_owned(),
+                ))
+                .with_status(200)
+                .with_body("[{\"advice\":{\"reconnect\":\"retry\"},\"channel\":\"/meta/connect\",\"error\":\"400::Error\",\"successful\":false}]")
+                .expect(retries as usize + 1)
+                .create();
+            let mut client = client().set_auto_disconnect(false).set_retries(retries);
+
+            client.init().expect("Could not init client");
+            let err = client.connect().expect_err("Connect should not return Ok");
+
+            assert_eq!(err.kind(), ErrorKind::MaxRetriesReached);
+            connect_mock.assert();
+        }
+    }
+
+    #[test]
+    fn estimates_clock_offset_with_timesync() {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let tc = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Could not get time")
+            .as_millis() as i64;
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::Regex(
+                "\"timesync\":\\{\"l\":0,\"o\":0,\"tc\":".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(format!(
+                "[{{\"channel\":\"/meta/connect\",\"successful\":true,\"ext\":{{\"timesync\":{{\"tc\":{},\"ts\":{},\"p\":0,\"a\":0}}}}}}]",
+                tc,
+                tc + 60_000
+            ))
+            .create();
+        let mut client = client().set_auto_disconnect(false).enable_timesync(true);
+
+        assert_eq!(client.clock_offset(), None);
+        client.init().expect("Could not init client");
+        client.connect().expect("Could not connect");
+
+        let lag = client.network_lag().expect("Lag should be estimated");
+        assert!(lag < 1_000);
+        assert_eq!(client.clock_offset(), Some(60_000 - i64::from(lag)));
+        connect_mock.assert();
+    }
+
+    #[test]
+    fn sends_back_ack_id() {
+        let hs_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\",\"ext\":{\"ack\":true}}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let first_connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\",\"ext\":{\"ack\":-1}}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/connect\",\"successful\":true,\"ext\":{\"ack\":5}}]")
+            .expect(1)
+            .create();
+        let second_connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\",\"ext\":{\"ack\":5}}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/connect\",\"successful\":true,\"ext\":{\"ack\":6}}]")
+            .expect(1)
+            .create();
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .enable_ack_extension(true);
+
+        client.init().expect("Could not init client");
+        client.connect().expect("Could not connect");
+        client.connect().expect("Could not connect");
+
+        hs_mock.assert();
+        first_connect_mock.assert();
+        second_connect_mock.assert();
+    }
+
+    #[test]
+    fn rejects_too_many_messages() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let deliveries = (0..100)
+            .map(|id| {
+                serde_json::json!({ "channel": "/topic/a", "data": { "event": { "replayId": id } } })
+            })
+            .chain(std::iter::once(
+                serde_json::json!({ "channel": "/meta/connect", "successful": true }),
+            ))
+            .collect::<Vec<_>>();
+        let _connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(serde_json::Value::Array(deliveries).to_string())
+            .create();
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .set_replay("/topic/a", -1)
+            .set_max_messages_per_poll(10);
+
+        client.init().expect("Could not init client");
+        let err = client.connect().expect_err("Connect should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Protocol);
+        // The rejected events must be replayed on the next subscription.
+        assert_eq!(client.replay_id("/topic/a"), Some(-1));
+    }
+
+    #[test]
+    fn resets_retries_before_each_operation() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"advice\":{\"reconnect\":\"retry\"},\"channel\":\"/meta/connect\",\"error\":\"400::Error\",\"successful\":false}]")
+            .create();
+        let _publish_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/topic/a\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/topic/a\",\"successful\":true,\"clientId\":\"1234\",\"data\":{}}]")
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+
+        client.init().expect("Could not init client");
+        assert_eq!(client.retries_used(), 0);
+        client.connect().expect_err("Connect should not return Ok");
+        assert_eq!(client.retries_used(), RETRIES_MAX);
+        client
+            .publish("/topic/a", serde_json::json!({}))
+            .expect("Could not publish");
+        assert_eq!(client.retries_used(), 0);
+        client.connect().expect_err("Connect should not return Ok");
+        client.reset_retries();
+        assert_eq!(client.retries_used(), 0);
+    }
+
+    #[test]
+    fn counts_transport_retries() {
+        let mut client = Client::new("http://127.0.0.1:1", "1234", Duration::from_secs(1))
+            .expect("Could not build cometd client")
+            .set_auto_disconnect(false)
+            .set_transport_retries(2);
+
+        client.init().expect_err("Init should not return Ok");
+        assert_eq!(client.transport_retries_used(), 2);
+        assert_eq!(client.retries_used(), 0);
+        client.reset_retries();
+        assert_eq!(client.transport_retries_used(), 0);
+    }
+
+    #[test]
+    fn calls_hooks_on_reconnect_and_handshake() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"advice\":{\"reconnect\":\"retry\"},\"channel\":\"/meta/connect\",\"error\":\"400::Error\",\"successful\":false}]")
+            .create();
+        let reconnects = Arc::new(AtomicUsize::new(0));
+        let handshakes = Arc::new(AtomicUsize::new(0));
+        let (reconnects_hook, handshakes_hook) = (reconnects.clone(), handshakes.clone());
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .on_reconnect(Box::new(move || {
+                reconnects_hook.fetch_add(1, Ordering::SeqCst);
+            }))
+            .on_handshake(Box::new(move |resp| {
+                assert_eq!(resp.client_id, "1234");
+                handshakes_hook.fetch_add(1, Ordering::SeqCst);
+            }));
+
+        client.init().expect("Could not init client");
+        client.connect().expect_err("Connect should not return Ok");
+
+        assert_eq!(reconnects.load(Ordering::SeqCst), RETRIES_MAX as usize);
+        assert_eq!(handshakes.load(Ordering::SeqCst), 1);
+    }
+
+    fn retry_with_interval(interval: &str, default_interval: Duration) -> Duration {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(format!("[{{\"advice\":{{\"reconnect\":\"retry\"{}}},\"channel\":\"/meta/connect\",\"error\":\"400::Error\",\"successful\":false}}]", interval))
+            .create();
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .set_advice_reconnects(1)
+            .set_default_interval(default_interval);
+
+        client.init().expect("Could not init client");
+        let start = Instant::now();
+        client.connect().expect_err("Connect should not return Ok");
+        start.elapsed()
+    }
+
+    #[test]
+    fn reconnects_right_away_on_zero_interval() {
+        let elapsed = retry_with_interval(",\"interval\":0", Duration::from_secs(5));
+
+        assert!(elapsed < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn waits_default_interval_without_advised_one() {
+        let elapsed = retry_with_interval("", Duration::from_millis(300));
+
+        assert!(elapsed >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn returns_messages_received_with_none_advice() {
+        use crate::state::ClientState;
+
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/topic/a\",\"data\":1},{\"advice\":{\"reconnect\":\"none\"},\"channel\":\"/meta/connect\",\"error\":\"403::Session ended\",\"successful\":false}]")
+            .expect(1)
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+
+        client.init().expect("Could not init client");
+        let resps = client.connect().expect("Could not connect");
+
+        assert_eq!(resps.len(), 1);
+        assert_eq!(resps[0].channel(), "/topic/a");
+        assert_eq!(client.client_id(), None);
+        assert_eq!(client.state(), ClientState::Disconnected);
+        connect_mock.assert();
+    }
+
+    #[test]
+    fn follows_many_advices_in_a_row() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
+            )
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"advice\":{\"reconnect\":\"retry\"},\"channel\":\"/meta/connect\",\"error\":\"400::Error\",\"successful\":false}]")
+            .expect(101)
+            .create();
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .set_advice_reconnects(100);
+
+        client.init().expect("Could not init client");
+        let err = client.connect().expect_err("Connect should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::MaxRetriesReached);
+        connect_mock.assert();
+    }
+
+    #[test]
+    fn retries_on_transport_failure() {
+        use std::net::TcpListener;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind listener");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let server_attempts = attempts.clone();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                server_attempts.fetch_add(1, Ordering::SeqCst);
+                drop(stream);
+            }
+        });
+        let mut client = Client::new(&url, VALID_ACCESS_TOKEN, Duration::from_secs(1))
+            .expect("Could not build cometd client")
+            .set_transport_retries(2)
+            .set_advice_reconnects(0);
+        let err = client.init().expect_err("Init should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Transport);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn treats_timeout_as_empty_poll() {
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind listener");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        std::thread::spawn(move || {
+            let mut pending = vec![];
+
+            for stream in listener.incoming() {
+                let mut stream = stream.expect("Could not accept connection");
+                let request = read_http_request(&mut stream);
+
+                if request.contains("/meta/handshake") {
+                    write_http_response(&mut stream, HANDSHAKE_RESPONSE);
+                } else {
+                    // Keep the connect request hanging.
+                    pending.push(stream);
+                }
+            }
+        });
+        let mut client = Client::new(&url, VALID_ACCESS_TOKEN, Duration::from_secs(5))
+            .expect("Could not build cometd client")
+            .set_auto_disconnect(false)
+            .set_treat_timeout_as_empty(true);
+
+        client.init().expect("Could not init client");
+        let resps = client
+            .connect_with_timeout(Duration::from_millis(200))
+            .expect("Could not connect");
+
+        assert!(resps.is_empty());
+    }
+
+    #[test]
+    fn cancels_pending_long_poll() {
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind listener");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        std::thread::spawn(move || {
+            let mut pending = vec![];
+
+            for stream in listener.incoming() {
+                let mut stream = stream.expect("Could not accept connection");
+                let request = read_http_request(&mut stream);
+
+                if request.contains("/meta/handshake") {
+                    write_http_response(&mut stream, HANDSHAKE_RESPONSE);
+                } else {
+                    // Keep the connect request hanging.
+                    pending.push(stream);
+                }
+            }
+        });
+        let mut client = Client::new(&url, VALID_ACCESS_TOKEN, Duration::from_secs(30))
+            .expect("Could not build cometd client")
+            .set_auto_disconnect(false);
+        let handle = client.cancellation_handle();
+
+        client.init().expect("Could not init client");
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            handle.cancel();
+        });
+        let start = Instant::now();
+        let err = client.connect().expect_err("Connect should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Cancelled);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn connects_after_transport_failure() {
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind listener");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        std::thread::spawn(move || {
+            let mut failed = false;
+
+            for stream in listener.incoming() {
+                let mut stream = stream.expect("Could not accept connection");
+                let request = read_http_request(&mut stream);
+
+                if request.contains("/meta/handshake") {
+                    write_http_response(&mut stream, HANDSHAKE_RESPONSE);
+                } else if !failed {
+                    // Drop the first connect request without answering.
+                    failed = true;
+                } else {
+                    write_http_response(
+                        &mut stream,
+                        "[{\"channel\":\"/meta/connect\",\"successful\":true}]",
+                    );
+                }
+            }
+        });
+        let mut client = Client::new(&url, VALID_ACCESS_TOKEN, Duration::from_secs(1))
+            .expect("Could not build cometd client")
+            .set_auto_disconnect(false)
+            .set_transport_retries(1)
+            .set_backoff(Duration::from_millis(10), Duration::from_millis(100));
+
+        client.init().expect("Could not init client");
+        let resps = client.connect().expect("Could not connect");
+
+        assert_eq!(resps.len(), 1);
+    }
+
+    #[test]
+    fn fails_over_to_advised_host() {
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind listener");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handshake = format!(
+            "[{{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"],\"advice\":{{\"reconnect\":\"retry\",\"hosts\":[\"{}\"]}}}}]",
+            mockito::server_url()
+        );
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.expect("Could not accept connection");
+                let request = read_http_request(&mut stream);
+
+                // The primary server goes down once the client handshaked.
+                if request.contains("/meta/handshake") {
+                    write_http_response(&mut stream, &handshake);
+                }
+            }
+        });
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/connect\",\"successful\":true}]")
+            .create();
+        let mut client = Client::new(&url, VALID_ACCESS_TOKEN, Duration::from_secs(1))
+            .expect("Could not build cometd client")
+            .set_auto_disconnect(false)
+            .set_follow_advice_hosts(true);
+
+        client.init().expect("Could not init client");
+        let resps = client.connect().expect("Could not connect");
+
+        assert_eq!(resps.len(), 1);
+        assert_eq!(client.base_url(), format!("{}/", mockito::server_url()));
+        connect_mock.assert();
+    }
+
+    #[test]
+    fn backs_off_exponentially() {
+        use std::net::TcpListener;
+        use std::sync::mpsc;
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind listener");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            for (attempt, stream) in listener.incoming().enumerate() {
+                let mut stream = stream.expect("Could not accept connection");
+
+                tx.send(Instant::now()).unwrap();
+                if attempt < 2 {
+                    continue;
+                }
+                read_http_request(&mut stream);
+                write_http_response(&mut stream, HANDSHAKE_RESPONSE);
+            }
+        });
+        let mut client = Client::new(&url, VALID_ACCESS_TOKEN, Duration::from_secs(1))
+            .expect("Could not build cometd client")
+            .set_auto_disconnect(false)
+            .set_transport_retries(2)
+            .set_backoff(Duration::from_millis(50), Duration::from_secs(1));
+
+        client.init().expect("Could not init client");
+        let attempts = rx.try_iter().collect::<Vec<_>>();
+
+        assert_eq!(attempts.len(), 3);
+        let first_delay = attempts[1] - attempts[0];
+        let second_delay = attempts[2] - attempts[1];
+        assert!(first_delay >= Duration::from_millis(50));
+        assert!(second_delay > first_delay);
+    }
+
+    #[test]
+    fn waits_for_idle_connect_held_for_advised_timeout() {
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind listener");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.expect("Could not accept connection");
+                let request = read_http_request(&mut stream);
+
+                if request.contains("/meta/handshake") {
+                    write_http_response(&mut stream, "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"],\"advice\":{\"reconnect\":\"retry\",\"interval\":0,\"timeout\":500}}]");
+                } else {
+                    // Hold the connect for exactly the advised timeout, as an idle server does.
+                    std::thread::sleep(Duration::from_millis(500));
+                    write_http_response(
+                        &mut stream,
+                        "[{\"channel\":\"/meta/connect\",\"successful\":true}]",
+                    );
+                }
+            }
+        });
+        let mut client = Client::new(&url, VALID_ACCESS_TOKEN, Duration::from_millis(300))
+            .expect("Could not build cometd client")
+            .set_auto_disconnect(false)
+            .set_retries(0)
+            .set_max_network_delay(Duration::from_millis(500));
+
+        client.init().expect("Could not init client");
+        assert_eq!(client.current_timeout(), Duration::from_secs(1));
+        client.connect().expect("Could not connect");
+    }
+
+    #[test]
+    fn connects_with_one_off_timeout() {
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind listener");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        std::thread::spawn(move || {
+            let mut streams = vec![];
+
+            for stream in listener.incoming() {
+                let mut stream = stream.expect("Could not accept connection");
+                let request = read_http_request(&mut stream);
+
+                if request.contains("/meta/handshake") {
+                    write_http_response(&mut stream, HANDSHAKE_RESPONSE);
+                } else {
+                    // Never answer the connect request.
+                    streams.push(stream);
+                }
+            }
+        });
+        let mut client = Client::new(&url, VALID_ACCESS_TOKEN, Duration::from_secs(120))
+            .expect("Could not build cometd client")
+            .set_auto_disconnect(false)
+            .set_retries(0);
+
+        client.init().expect("Could not init client");
+        let start = Instant::now();
+        let err = client
+            .connect_with_timeout(Duration::from_millis(200))
+            .expect_err("Connect should time out");
+
+        assert_eq!(err.kind(), ErrorKind::Transport);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(client.current_timeout(), Duration::from_secs(120));
+    }
+
+    #[test]
+    fn uses_custom_connection_type() {
+        let hs_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\",\"supportedConnectionTypes\":[\"callback-polling\"]}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\",\"callback-polling\"]}]",
+            )
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\",\"connectionType\":\"callback-polling\"}"
+                    .to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/connect\",\"successful\":true}]")
+            .create();
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .set_connection_type("callback-polling");
+
+        client.init().expect("Could not init client");
+        client.connect().expect("Could not connect");
+        hs_mock.assert();
+        connect_mock.assert();
+    }
+
+    #[test]
+    fn advertises_several_connection_types() {
+        let hs_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\",\"supportedConnectionTypes\":[\"callback-polling\",\"long-polling\"]}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
+            )
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\",\"connectionType\":\"long-polling\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/connect\",\"successful\":true}]")
+            .create();
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .set_supported_connection_types(vec![
+                "callback-polling".to_owned(),
+                "long-polling".to_owned(),
+            ])
+            .expect("Could not set connection types");
+
+        client.init().expect("Could not init client");
+        client.connect().expect("Could not connect");
+        hs_mock.assert();
+        connect_mock.assert();
+    }
+
+    #[test]
+    fn rejects_connection_types_the_transport_cannot_carry() {
+        let err = client()
+            .set_supported_connection_types(vec!["websocket".to_owned(), "long-polling".to_owned()])
+            .err()
+            .expect("Connection types should be rejected");
+
+        assert_eq!(err.kind(), ErrorKind::Transport);
+    }
+
+    #[test]
+    fn posts_to_endpoint_paths() {
+        let hs_mock = mock("POST", "/handshake")
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let connect_mock = mock("POST", "/connect")
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/connect\",\"successful\":true}]")
+            .create();
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .set_endpoint_path("/meta/handshake", "/handshake")
+            .set_endpoint_path("/meta/connect", "connect");
+
+        client.init().expect("Could not init client");
+        client.connect().expect("Could not connect");
+        hs_mock.assert();
+        connect_mock.assert();
+    }
+
+    #[test]
+    fn stamps_messages_with_ids() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\",\"id\":\"1\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"],\"id\":\"1\"}]",
+            )
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\",\"id\":\"2\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/connect\",\"successful\":true,\"id\":\"2\"}]")
+            .create();
+        let mut client = client();
+
+        client.init().expect("Could not init client");
+        client.connect().expect("Could not connect");
+        assert_eq!(client.message_id(), 2);
+        connect_mock.assert();
+    }
+
+    #[test]
+    fn rejects_mismatching_id() {
+        let _m = mock("POST", "/")
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"],\"id\":\"42\"}]",
+            )
+            .create();
+        let mut client = client();
+        let err = client.init().expect_err("Init should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Protocol);
+    }
+
+    #[test]
+    fn waits_for_advised_interval() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"supportedConnectionTypes\":[\"long-polling\"]}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
+            )
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\",\"clientId\":\"1234\",\"connectionType\":\"long-polling\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"advice\":{\"reconnect\":\"retry\",\"interval\":500},\"channel\":\"/meta/connect\",\"error\":\"400::Error\",\"successful\":false}]")
+            .expect(2)
+            .create();
+        let mut client = client().set_retries(1);
+
+        client.init().expect("Could not init client");
+        let start = Instant::now();
+        client.connect().expect_err("Connect should not return Ok");
+
+        assert!(start.elapsed() >= Duration::from_millis(500));
+        connect_mock.assert();
+    }
+
+    #[test]
+    fn keeps_advice_of_successful_response() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"advice\":{\"reconnect\":\"retry\",\"interval\":0,\"timeout\":30000},\"channel\":\"/meta/connect\",\"successful\":true}]")
+            .expect(1)
+            .create();
+        let mut client = client();
+
+        client.init().expect("Could not init client");
+        let resps = client.connect().expect("Could not connect");
+
+        connect_mock.assert();
+        assert_eq!(resps.len(), 1);
+        assert_eq!(
+            resps[0].advice().and_then(|advice| advice.interval),
+            Some(0)
+        );
+        assert_eq!(client.current_timeout(), Duration::from_secs(40));
+    }
+
+    #[test]
+    fn applies_advised_timeout() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"supportedConnectionTypes\":[\"long-polling\"]}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
+            )
+            .create();
+        let _connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\",\"clientId\":\"1234\",\"connectionType\":\"long-polling\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"advice\":{\"reconnect\":\"none\",\"timeout\":30000},\"channel\":\"/meta/connect\",\"error\":\"400::Error\",\"successful\":false}]")
+            .create();
+        let mut client = client();
+
+        client.init().expect("Could not init client");
+        assert_eq!(client.current_timeout(), Duration::from_secs(120));
+        client.connect().expect_err("Connect should not return Ok");
+        assert_eq!(client.current_timeout(), Duration::from_secs(40));
+    }
+
+    fn unknown_client_mocks(handshakes: usize) -> (mockito::Mock, mockito::Mock) {
+        let hs_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .expect(handshakes)
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/connect\",\"successful\":false,\"error\":\"402::Unknown client\"}]")
+            .create();
+
+        (hs_mock, connect_mock)
+    }
+
+    #[test]
+    fn handshakes_on_unknown_client_if_enabled() {
+        let (hs_mock, _connect_mock) = unknown_client_mocks(2);
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .set_advice_reconnects(1)
+            .set_auto_rehandshake_on_unknown_client(true);
+
+        client.init().expect("Could not init client");
+        let err = client.connect().expect_err("Connect should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::MaxRetriesReached);
+        hs_mock.assert();
+    }
+
+    #[test]
+    fn does_not_handshake_on_unknown_client_by_default() {
+        let (hs_mock, _connect_mock) = unknown_client_mocks(1);
+        let mut client = client().set_auto_disconnect(false);
+
+        client.init().expect("Could not init client");
+        let err = client.connect().expect_err("Connect should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Protocol);
+        hs_mock.assert();
+    }
+
+    #[test]
+    fn handshake_if_advises_to() {
+        let hs_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"supportedConnectionTypes\":[\"long-polling\"]}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
+            )
+            .expect(RETRIES_MAX as usize + 1) // The first handshake then one per retry
+            .create();
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\",\"clientId\":\"1234\",\"connectionType\":\"long-polling\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(
+                "[{\"advice\":{\"reconnect\":\"handshake\"},\"channel\":\"/meta/connect\",\"successful\":false,\"error\":\"error\"}]",
+            )
+            .create();
+        let mut client = client();
+
+        client.init().expect("Could not init client");
+        let resp = client.connect().expect_err("Connect should not return Ok");
+        println!("{:#?}", resp);
+        hs_mock.assert();
+    }
+}
+
+#[cfg(feature = "async")]
+mod async_client {
+    use super::*;
+    use crate::client::async_client::AsyncClient;
+
+    fn client() -> AsyncClient {
+        AsyncClient::new(
+            &mockito::server_url(),
+            VALID_ACCESS_TOKEN,
+            Duration::from_secs(120),
+        )
+        .expect("Could not build async cometd client")
+        .set_retries(RETRIES_MAX)
+    }
+
+    #[tokio::test]
+    async fn init_works() {
+        let _m = mock("POST", "/")
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
+            )
+            .create();
+        let mut client = client();
+
+        assert!(client.init().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn retries_if_server_advises_to() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"supportedConnectionTypes\":[\"long-polling\"]}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
+            )
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\",\"clientId\":\"1234\",\"connectionType\":\"long-polling\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"advice\":{\"reconnect\":\"retry\"},\"channel\":\"/meta/connect\",\"error\":\"400::Error\",\"successful\":false}]")
+            .expect(RETRIES_MAX as usize + 1)
+            .create();
+        let mut client = client();
+
+        client.init().await.expect("Could not init client");
+        client
+            .connect()
+            .await
+            .expect_err("Connect should not return Ok");
+        connect_mock.assert();
+    }
+
+    #[tokio::test]
+    async fn sends_publish_again_when_advised_to_retry() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let publish_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/topic/a\",\"clientId\":\"1234\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"advice\":{\"reconnect\":\"retry\"},\"channel\":\"/topic/a\",\"error\":\"400::Error\",\"successful\":false}]")
+            .expect(RETRIES_MAX as usize + 1)
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .expect(0)
+            .create();
+        let mut client = client();
+
+        client.init().await.expect("Could not init client");
+        let err = client
+            .publish("/topic/a", 1)
+            .await
+            .expect_err("Publish should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::MaxRetriesReached);
+        publish_mock.assert();
+        connect_mock.assert();
+    }
+
+    #[tokio::test]
+    async fn fails_version_negotiation() {
+        let _m = mock("POST", "/")
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/handshake\",\"successful\":false,\"error\":\"406::Unsupported version\"}]")
+            .create();
+        let mut client = client();
+        let err = client.init().await.expect_err("Init should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::VersionNegotiation);
+    }
+}
+
+mod listen {
+    use super::*;
+    use crate::response::Response;
+
+    #[test]
+    fn delivers_messages_until_disconnected() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
+            )
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/topic/a\",\"data\":{\"value\":1}},{\"channel\":\"/meta/disconnect\",\"successful\":true}]")
+            .expect(1)
+            .create();
+        let mut client = client();
+        let mut received = vec![];
+
+        client.init().expect("Could not init client");
+        client
+            .listen(|resp| received.push(resp))
+            .expect("Could not listen");
+
+        connect_mock.assert();
+        assert_eq!(received.len(), 1);
+        match &received[0] {
+            Response::Delivery(resp) => assert_eq!(resp.channel, "/topic/a"),
+            resp => panic!("Unexpected response {:?}", resp),
+        }
+        assert_eq!(client.client_id(), None);
+    }
+
+    #[test]
+    fn stops_listening_when_asked_to() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/topic/a\",\"data\":{\"value\":1}},{\"channel\":\"/meta/connect\",\"successful\":true}]")
+            .expect(1)
+            .create();
+        let disconnect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/disconnect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/disconnect\",\"successful\":true}]")
+            .expect(1)
+            .create();
+        let mut client = client();
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut received = 0;
+
+        client.init().expect("Could not init client");
+        client
+            .listen_until(
+                |_| {
+                    received += 1;
+                    stop.store(true, Ordering::SeqCst);
+                },
+                stop.clone(),
+            )
+            .expect("Could not listen");
+
+        assert_eq!(received, 1);
+        assert_eq!(client.client_id(), None);
+        connect_mock.assert();
+        disconnect_mock.assert();
+    }
+
+    #[test]
+    fn reports_idle_channels() {
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/connect\",\"successful\":true}]")
+            .create();
+        let _disconnect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/disconnect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/disconnect\",\"successful\":true}]")
+            .create();
+        let idle = Arc::new(AtomicUsize::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (on_idle_count, on_idle_stop) = (idle.clone(), stop.clone());
+        let mut client = client()
+            .set_idle_timeout(Duration::from_millis(50))
+            .set_default_interval(Duration::from_millis(10))
+            .on_idle(Box::new(move |elapsed| {
+                assert!(elapsed >= Duration::from_millis(50));
+                on_idle_count.fetch_add(1, Ordering::SeqCst);
+                on_idle_stop.store(true, Ordering::SeqCst);
+            }));
+        let mut received = 0;
+
+        client.init().expect("Could not init client");
+        client
+            .listen_until(|_| received += 1, stop)
+            .expect("Could not listen");
+
+        assert_eq!(received, 0);
+        assert_eq!(idle.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn keeps_most_recent_messages() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/topic/a\",\"data\":1},{\"channel\":\"/topic/a\",\"data\":2},{\"channel\":\"/topic/a\",\"data\":3},{\"channel\":\"/meta/connect\",\"successful\":true}]")
+            .create();
+        let mut client = client().set_auto_disconnect(false).set_buffer_size(2);
+
+        client.init().expect("Could not init client");
+        client.connect().expect("Could not connect");
+        let data = client
+            .recent_messages()
+            .into_iter()
+            .map(|resp| match resp {
+                Response::Delivery(resp) => resp.data,
+                resp => panic!("Unexpected response {:?}", resp),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(data, vec![serde_json::json!(2), serde_json::json!(3)]);
+    }
+
+    #[test]
+    fn iterates_over_messages() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/topic/a\",\"data\":1},{\"channel\":\"/topic/b\",\"data\":2},{\"channel\":\"/meta/disconnect\",\"successful\":true}]")
+            .create();
+        let mut client = client();
+
+        client.init().expect("Could not init client");
+        let channels = client
+            .messages()
+            .map(|resp| match resp.expect("Could not receive message") {
+                Response::Delivery(resp) => resp.channel,
+                resp => panic!("Unexpected response {:?}", resp),
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(channels, vec!["/topic/a", "/topic/b"]);
+    }
+
+    #[test]
+    fn yields_error_once() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"advice\":{\"reconnect\":\"none\"},\"channel\":\"/meta/connect\",\"successful\":false,\"error\":\"500::Error\"}]")
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+
+        client.init().expect("Could not init client");
+        let resps = client.messages().collect::<Vec<_>>();
+
+        assert_eq!(resps.len(), 1);
+        assert_eq!(
+            resps[0].as_ref().err().map(|err| err.kind()),
+            Some(ErrorKind::ServerAdvisedStop)
+        );
+    }
+
+    #[test]
+    fn requires_handshake() {
+        let err = client()
+            .listen(|_| {})
+            .expect_err("Listen should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::NotHandshaked);
+    }
+}
+
+mod headers {
+    use super::*;
+
+    #[test]
+    fn sends_json_content_type_and_accept() {
+        let m = mock("POST", "/")
+            .match_header("content-type", "application/json")
+            .match_header("accept", "application/json")
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let mut client = client();
+
+        client.init().expect("Could not init client");
+        m.assert();
+    }
+
+    #[test]
+    fn sends_custom_header_on_every_request() {
+        let hs_mock = mock("POST", "/")
+            .match_header("x-tenant-id", "42")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
+            )
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_header("x-tenant-id", "42")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/connect\",\"successful\":true}]")
+            .create();
+        let publish_mock = mock("POST", "/")
+            .match_header("x-tenant-id", "42")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/topic/a\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/topic/a\",\"successful\":true}]")
+            .create();
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .set_header("X-Tenant-Id", "42")
+            .expect("Could not set header");
+
+        client.init().expect("Could not init client");
+        client.connect().expect("Could not connect");
+        client
+            .publish("/topic/a", "hello")
+            .expect("Could not publish");
+        hs_mock.assert();
+        connect_mock.assert();
+        publish_mock.assert();
+    }
+
+    #[test]
+    fn overrides_auth_header() {
+        let m = mock("POST", "/")
+            .match_header("authorization", "Token abcd")
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
+            )
+            .create();
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .set_header("Authorization", "Token abcd")
+            .expect("Could not set header");
+
+        client.init().expect("Could not init client");
+        m.assert();
+    }
+
+    #[test]
+    fn rejects_invalid_header_name() {
+        let err = client()
+            .set_header("X Tenant", "42")
+            .err()
+            .expect("Header name should be invalid");
+
+        assert_eq!(err.kind(), ErrorKind::Parse);
+    }
+}
+
+mod cookies {
+    use super::*;
+
+    #[test]
+    fn stores_cookies_from_handshake() {
+        let _m = mock("POST", "/")
+            .with_status(200)
+            .with_header("set-cookie", "BAYEUX_BROWSER=abcd")
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
+            )
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+
+        client.init().expect("Could not init client");
+        assert_eq!(client.cookies(), ["BAYEUX_BROWSER=abcd".to_owned()]);
+    }
+
+    #[test]
+    fn sends_cookies_set_after_handshake() {
+        let _handshake = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_header("set-cookie", "A=1")
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _connect = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_header("set-cookie", "B=2")
+            .with_body("[{\"channel\":\"/meta/connect\",\"successful\":true}]")
+            .create();
+        let publish = mock("POST", "/")
+            .match_header("cookie", "A=1; B=2")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/topic/a\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/topic/a\",\"successful\":true}]")
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+
+        client.init().expect("Could not init client");
+        client.connect().expect("Could not connect");
+        client.publish("/topic/a", 1).expect("Could not publish");
+        assert_eq!(client.cookies(), ["A=1".to_owned(), "B=2".to_owned()]);
+        publish.assert();
+    }
+
+    #[test]
+    fn sends_cookie_header() {
+        let m = mock("POST", "/")
+            .match_header("cookie", "BAYEUX_BROWSER=abcd; JSESSIONID=efgh")
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
+            )
+            .create();
+        let mut client = client().set_auto_disconnect(false).set_cookies(vec![
+            "BAYEUX_BROWSER=abcd".to_owned(),
+            "JSESSIONID=efgh".to_owned(),
+        ]);
+
+        client.init().expect("Could not init client");
+        m.assert();
+    }
+
+    #[test]
+    fn keeps_restored_cookies() {
+        let _m = mock("POST", "/")
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
+            )
+            .create();
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .set_cookies(vec!["BAYEUX_BROWSER=abcd".to_owned()]);
+
+        assert_eq!(client.cookies(), ["BAYEUX_BROWSER=abcd".to_owned()]);
+        client.init().expect("Could not init client");
+        assert_eq!(client.cookies(), ["BAYEUX_BROWSER=abcd".to_owned()]);
+    }
+}
+
+mod drop {
+    use super::*;
+
+    fn disconnect_mock(hits: usize) -> (mockito::Mock, mockito::Mock) {
+        let hs_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
+            )
+            .create();
+        let disconnect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/disconnect\",\"clientId\":\"1234\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/disconnect\",\"successful\":true}]")
+            .expect(hits)
+            .create();
+
+        (hs_mock, disconnect_mock)
+    }
+
+    #[test]
+    fn disconnects_when_dropped() {
+        let (_hs_mock, disconnect_mock) = disconnect_mock(1);
+        let mut client = client();
+
+        client.init().expect("Could not init client");
+        std::mem::drop(client);
+        disconnect_mock.assert();
+    }
+
+    #[test]
+    fn does_not_disconnect_if_disabled() {
+        let (_hs_mock, disconnect_mock) = disconnect_mock(0);
+        let mut client = client().set_auto_disconnect(false);
+
+        client.init().expect("Could not init client");
+        std::mem::drop(client);
+        disconnect_mock.assert();
+    }
+
+    #[test]
+    fn does_not_disconnect_twice() {
+        let (_hs_mock, disconnect_mock) = disconnect_mock(1);
+        let mut client = client();
+
+        client.init().expect("Could not init client");
+        client.disconnect().expect("Could not disconnect");
+        std::mem::drop(client);
+        disconnect_mock.assert();
+    }
+}
+
+mod metrics {
+    use super::*;
+    use crate::metrics::Metrics;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct RecordingMetrics {
+        counters: Mutex<Vec<String>>,
+    }
+
+    impl RecordingMetrics {
+        fn count(&self, name: &str) -> usize {
+            let counters = self.counters.lock().unwrap();
+            counters.iter().filter(|counter| *counter == name).count()
+        }
+    }
+
+    impl Metrics for RecordingMetrics {
+        fn incr(&self, name: &str) {
+            self.counters.lock().unwrap().push(name.to_owned());
+        }
+    }
+
+    #[test]
+    fn counts_requests_retries_and_errors() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"advice\":{\"reconnect\":\"retry\"},\"channel\":\"/meta/connect\",\"error\":\"400::Error\",\"successful\":false}]")
+            .create();
+        let metrics = Arc::new(RecordingMetrics::default());
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .set_metrics(metrics.clone());
+
+        client.init().expect("Could not init client");
+        client.connect().expect_err("Connect should not return Ok");
+
+        assert_eq!(metrics.count("requests"), RETRIES_MAX as usize + 2);
+        assert_eq!(metrics.count("handshakes"), 1);
+        assert_eq!(metrics.count("retries"), RETRIES_MAX as usize);
+        assert_eq!(metrics.count("errors.max_retries_reached"), 1);
+    }
+}
+
+mod session {
+    use super::*;
+    use crate::client::Session;
+
+    #[test]
+    fn publishes_concurrently() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Client>();
+        assert_send_sync::<Session>();
+
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let publish_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/topic/a\",\"clientId\":\"1234\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/topic/a\",\"successful\":true}]")
+            .expect(4)
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+        let session = client.session();
+
+        client.init().expect("Could not init client");
+        let handles = (0..4)
+            .map(|i| {
+                let session = session.clone();
+
+                std::thread::spawn(move || session.publish("/topic/a", i))
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            handle
+                .join()
+                .expect("Publishing thread panicked")
+                .expect("Could not publish");
+        }
+        publish_mock.assert();
+    }
+
+    #[test]
+    fn requires_handshake() {
+        let mut client = client();
+        let err = client
+            .session()
+            .publish("/topic/a", 1)
+            .expect_err("Publish should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::NotHandshaked);
+    }
+
+    #[test]
+    fn follows_headers_set_later() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let publish_mock = mock("POST", "/")
+            .match_header("x-tenant", "acme")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/topic/a\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/topic/a\",\"successful\":true}]")
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+        let session = client.session();
+        let mut client = client
+            .set_header("x-tenant", "acme")
+            .expect("Could not set header");
+
+        client.init().expect("Could not init client");
+        session.publish("/topic/a", 1).expect("Could not publish");
+        publish_mock.assert();
+    }
+}
+
+mod response {
+    use super::*;
+    use crate::response::{DeliveryResponse, ErroredResponse, PublishResponse, Response};
+    use serde::Deserialize;
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Update {
+        id: u32,
+        name: String,
+    }
+
+    fn errored_response(error: &str) -> ErroredResponse {
+        serde_json::from_value(serde_json::json!({
+            "channel": "/meta/connect",
+            "successful": false,
+            "error": error,
+        }))
+        .expect("Could not parse errored response")
+    }
+
+    #[test]
+    fn flags_unknown_fields_when_strict() {
+        let body = "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"],\"unexpected\":1}]";
+        let _m = mock("POST", "/").with_status(200).with_body(body).create();
+        let mut strict_client = client().set_strict_parsing(true);
+        let mut lenient_client = client().set_auto_disconnect(false);
+
+        let err = strict_client.init().expect_err("Init should not return Ok");
+        assert_eq!(err.kind(), ErrorKind::Parse);
+        assert!(err.message.contains("unknown field `unexpected`"));
+        assert_eq!(strict_client.client_id(), None);
+
+        lenient_client.init().expect("Could not init client");
+        assert_eq!(lenient_client.client_id(), Some("1234"));
+    }
+
+    #[test]
+    fn flags_unknown_advice_fields_when_strict() {
+        let _m = mock("POST", "/")
+            .with_status(200)
+            .with_body("{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"],\"advice\":{\"reconnect\":\"retry\",\"maxInterval\":10}}")
+            .create();
+        let mut client = client().set_strict_parsing(true);
+        let err = client.init().expect_err("Init should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Parse);
+        assert!(err.message.contains("unknown field `maxInterval`"));
+    }
+
+    #[cfg(feature = "arbitrary-precision")]
+    #[test]
+    fn keeps_exact_numbers() {
+        let body = "{\"channel\":\"/topic/a\",\"data\":{\"price\":12345678901234567890.123456789012345678}}";
+        let resp: Response = serde_json::from_str(body).expect("Could not parse response");
+
+        match resp {
+            Response::Delivery(resp) => {
+                assert_eq!(
+                    resp.data["price"].to_string(),
+                    "12345678901234567890.123456789012345678"
+                );
+                assert_eq!(
+                    serde_json::to_string(&resp.data).expect("Could not serialize data"),
+                    "{\"price\":12345678901234567890.123456789012345678}"
+                );
+            }
+            resp => panic!("Unexpected response {:?}", resp),
+        }
+    }
+
+    #[test]
+    fn exposes_channel_and_success_of_every_variant() {
+        let resps: Vec<Response> = serde_json::from_str(
+            "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]},\
+             {\"channel\":\"/topic/a\",\"clientId\":\"1234\",\"successful\":true,\"data\":{}},\
+             {\"channel\":\"/topic/b\",\"data\":{\"id\":1}},\
+             {\"channel\":\"/meta/connect\",\"successful\":false}]",
+        )
+        .expect("Could not parse responses");
+
+        assert!(matches!(resps[0], Response::Handshake(_)));
+        assert!(matches!(resps[1], Response::Publish(_)));
+        assert!(matches!(resps[2], Response::Delivery(_)));
+        assert!(matches!(resps[3], Response::Basic(_)));
+        assert_eq!(
+            resps.iter().map(Response::channel).collect::<Vec<_>>(),
+            vec!["/meta/handshake", "/topic/a", "/topic/b", "/meta/connect"]
+        );
+        assert_eq!(
+            resps
+                .iter()
+                .map(Response::is_successful)
+                .collect::<Vec<_>>(),
+            vec![Some(true), Some(true), None, Some(false)]
+        );
+        assert_eq!(resps.clone(), resps);
+    }
+
+    #[test]
+    fn deserializes_ext() {
+        #[derive(Deserialize, PartialEq, Debug)]
+        struct Ext {
+            source: String,
+            sequence: u64,
+        }
+
+        let resps: Vec<Response> = serde_json::from_str(
+            "[{\"channel\":\"/topic/a\",\"data\":{},\"ext\":{\"source\":\"feed\",\"sequence\":7}},\
+             {\"channel\":\"/topic/b\",\"data\":{}}]",
+        )
+        .expect("Could not parse responses");
+        let ext = resps[0]
+            .ext_as::<Ext>()
+            .expect("Response should have an ext")
+            .expect("Could not deserialize ext");
+
+        assert_eq!(
+            ext,
+            Ext {
+                source: "feed".to_owned(),
+                sequence: 7,
+            }
+        );
+        assert!(resps[0]
+            .ext_as::<Update>()
+            .expect("Response should have an ext")
+            .is_err());
+        assert!(resps[1].ext_as::<Ext>().is_none());
+    }
+
+    #[test]
+    fn displays_a_summary_of_every_variant() {
+        let resps: Vec<Response> = serde_json::from_str(
+            "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]},\
+             {\"channel\":\"/topic/a\",\"clientId\":\"1234\",\"successful\":true,\"data\":{}},\
+             {\"channel\":\"/topic/b\",\"data\":{\"id\":1}},\
+             {\"channel\":\"/meta/connect\",\"successful\":false}]",
+        )
+        .expect("Could not parse responses");
+
+        assert_eq!(
+            resps.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            vec![
+                "Handshake on /meta/handshake (successful)",
+                "Publish on /topic/a (successful)",
+                "Delivery on /topic/b",
+                "Basic on /meta/connect (unsuccessful)",
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_error_code_and_message() {
+        assert_eq!(
+            errored_response("402::Unknown client").error_parts(),
+            (Some(402), vec![], "Unknown client".to_owned())
+        );
+    }
+
+    #[test]
+    fn splits_error_arguments() {
+        assert_eq!(
+            errored_response("403::foo,bar::message").error_parts(),
+            (
+                Some(403),
+                vec!["foo".to_owned(), "bar".to_owned()],
+                "message".to_owned()
+            )
+        );
+        assert_eq!(
+            errored_response("403:/foo/bar:Subscription denied").error_parts(),
+            (
+                Some(403),
+                vec!["/foo/bar".to_owned()],
+                "Subscription denied".to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn keeps_free_form_error() {
+        assert_eq!(
+            errored_response("Something went wrong").error_parts(),
+            (None, vec![], "Something went wrong".to_owned())
+        );
+    }
+
+    #[test]
+    fn deserializes_delivery_data() {
+        let resp: DeliveryResponse =
+            serde_json::from_str("{\"channel\":\"/topic/a\",\"data\":{\"id\":1,\"name\":\"foo\"}}")
+                .expect("Could not parse delivery");
+
+        assert_eq!(
+            resp.data_as::<Update>()
+                .expect("Could not deserialize data"),
+            Update {
+                id: 1,
+                name: "foo".to_owned()
+            }
+        );
+    }
+
+    #[test]
+    fn deserializes_publish_data() {
+        let resp: PublishResponse = serde_json::from_str(
+            "{\"channel\":\"/topic/a\",\"clientId\":\"1234\",\"successful\":true,\"data\":{\"id\":2}}",
+        )
+        .expect("Could not parse publish response");
+        let err = resp.data_as::<Update>().expect_err("Data should not match");
+
+        assert_eq!(err.kind(), ErrorKind::Parse);
+    }
+}
+
+mod state {
+    use super::*;
+    use crate::state::ClientState;
+
+    #[test]
+    fn follows_the_session() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/connect\",\"successful\":true}]")
+            .create();
+        let _disconnect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/disconnect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/disconnect\",\"successful\":true}]")
+            .create();
+        let mut client = client();
+
+        assert_eq!(client.state(), ClientState::Unconnected);
+        client.init().expect("Could not init client");
+        assert_eq!(client.state(), ClientState::Handshaked);
+        client.connect().expect("Could not connect");
+        assert_eq!(client.state(), ClientState::Connected);
+        client.disconnect().expect("Could not disconnect");
+        assert_eq!(client.state(), ClientState::Disconnected);
+    }
+
+    #[test]
+    fn forgets_the_session_on_disconnect() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _subscribe_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/subscribe\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/subscribe\",\"successful\":true}]")
+            .create();
+        let _disconnect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/disconnect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/disconnect\",\"successful\":true}]")
+            .create();
+        let mut client = client().set_cookies(vec!["session=abcd".to_owned()]);
+
+        client.init().expect("Could not init client");
+        client.subscribe("/topic/a").expect("Could not subscribe");
+        client.disconnect().expect("Could not disconnect");
+
+        assert_eq!(client.client_id(), None);
+        assert!(client.cookies().is_empty());
+        let err = client.connect().expect_err("Connect should not return Ok");
+        assert_eq!(err.kind(), ErrorKind::NotHandshaked);
+        assert!(err.message.starts_with("No client id set"));
+    }
+
+    #[test]
+    fn stays_unconnected_if_handshake_fails() {
+        let _m = mock("POST", "/")
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/handshake\",\"successful\":false,\"error\":\"403::Forbidden\"}]")
+            .create();
+        let mut client = client();
+
+        client.init().expect_err("Init should not return Ok");
+        assert_eq!(client.state(), ClientState::Unconnected);
+    }
+}
+
+mod replay {
+    use super::*;
+
+    #[test]
+    fn sends_and_updates_replay_ids() {
+        let hs_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "channel": "/meta/handshake",
+                "ext": {"replay": {"/topic/a": -1}},
+            })))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let subscribe_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "channel": "/meta/subscribe",
+                "subscription": "/topic/a",
+                "ext": {"replay": {"/topic/a": -1}},
+            })))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/subscribe\",\"successful\":true,\"subscription\":\"/topic/a\"}]")
+            .create();
+        let _connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/topic/a\",\"data\":{\"event\":{\"replayId\":42},\"sobject\":{}}},{\"channel\":\"/topic/b\",\"data\":{\"event\":{\"replayId\":7}}}]")
+            .create();
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .set_replay("/topic/a", -1);
+
+        client.init().expect("Could not init client");
+        client.subscribe("/topic/a").expect("Could not subscribe");
+        client.connect().expect("Could not connect");
+        hs_mock.assert();
+        subscribe_mock.assert();
+        assert_eq!(client.replay_id("/topic/a"), Some(42));
+        assert_eq!(client.replay_id("/topic/b"), None);
+    }
+
+    #[test]
+    fn keeps_handshake_ext() {
+        let m = mock("POST", "/")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "ext": {"token": "1234", "replay": {"/topic/a": -2}},
+            })))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .set_handshake_ext(serde_json::json!({"token": "1234"}))
+            .set_replay("/topic/a", -2);
+
+        client.init().expect("Could not init client");
+        m.assert();
+    }
+}
+
+mod advice {
+    use crate::advice::Advice;
+
+    #[test]
+    fn deserializes_multiple_clients() {
+        let advice: Advice =
+            serde_json::from_str("{\"reconnect\":\"retry\",\"multiple-clients\":true}")
+                .expect("Could not deserialize advice");
+
+        assert_eq!(advice.multiple_clients, Some(true));
+    }
+}
+
+mod error {
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn displays_message() {
+        let err = Error::new(ErrorKind::Parse, "Could not parse response");
+
+        assert_eq!(err.to_string(), "Could not parse response");
+    }
+
+    #[test]
+    fn keeps_transport_error_as_source() {
+        let mut client = Client::new("http://127.0.0.1:1", "1234", Duration::from_secs(1))
+            .expect("Could not build cometd client");
+        let err = client.init().expect_err("Init should not return Ok");
+        let source = std::error::Error::source(&err).expect("Error should have a source");
+
+        assert_eq!(err.kind(), ErrorKind::Transport);
+        assert!(source.downcast_ref::<reqwest::Error>().is_some());
+    }
+
+    #[test]
+    fn converts_json_error() {
+        let json_err = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        let err = Error::from(json_err);
+        let source = std::error::Error::source(&err).expect("Error should have a source");
+
+        assert_eq!(err.kind(), ErrorKind::Parse);
+        assert!(source.downcast_ref::<serde_json::Error>().is_some());
+    }
+
+    #[test]
+    fn includes_unparsable_body() {
+        let body = format!("<html>{}</html>", "a".repeat(1000));
+        let _m = mock("POST", "/").with_status(200).with_body(&body).create();
+        let mut client = client();
+        let err = client.init().expect_err("Init should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Parse);
+        assert!(err.message.contains("expected value"));
+        assert!(err.message.contains("<html>aaa"));
+        assert!(err.message.len() < body.len());
+        assert_eq!(err.body, Some(body));
+        assert_eq!(err.status, None);
+    }
+
+    #[test]
+    fn leaves_out_unparsable_body_when_not_logging_bodies() {
+        let body = "<html>secret</html>";
+        let _m = mock("POST", "/").with_status(200).with_body(body).create();
+        let mut client = client().set_log_bodies(false);
+        let err = client.init().expect_err("Init should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Parse);
+        assert!(!err.message.contains("secret"));
+        assert_eq!(err.body.as_deref(), Some(body));
+    }
+
+    #[test]
+    fn reports_unauthorized_status() {
+        let body = "<html>Invalid token</html>";
+        let m = mock("POST", "/")
+            .with_status(401)
+            .with_body(body)
+            .expect(1)
+            .create();
+        let mut client = client();
+        let err = client.init().expect_err("Init should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Protocol);
+        assert!(err.message.contains("401 Unauthorized"));
+        assert!(err.message.contains("Invalid token"));
+        assert_eq!(err.body.as_deref(), Some(body));
+        assert_eq!(err.status, Some(401));
+        m.assert();
+    }
+
+    #[test]
+    fn reports_server_error_status() {
+        let _m = mock("POST", "/")
+            .with_status(500)
+            .with_body("<html>Internal error</html>")
+            .create();
+        let mut client = client().set_log_bodies(false);
+        let err = client.init().expect_err("Init should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Transport);
+        assert!(err.message.contains("500 Internal Server Error"));
+        assert!(!err.message.contains("Internal error"));
+        assert_eq!(err.status, Some(500));
+    }
+
+    #[test]
+    fn is_not_handshaked_before_init() {
+        let mut client = client();
+        let err = client
+            .unsubscribe("/topic/test")
+            .expect_err("Unsubscribe should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::NotHandshaked);
+    }
+
+    #[test]
+    fn does_not_use_message_ids_before_init() {
+        let mut client = client().set_auto_disconnect(false);
+
+        client
+            .unsubscribe("/topic/test")
+            .expect_err("Unsubscribe should not return Ok");
+        client
+            .publish("/topic/test", "hello")
+            .expect_err("Publish should not return Ok");
+        client
+            .disconnect()
+            .expect_err("Disconnect should not return Ok");
+        assert_eq!(client.message_id(), 0);
+    }
+
+    #[test]
+    fn converts_into_boxed_error() {
+        let err: Box<dyn std::error::Error> = Box::new(Error::new(ErrorKind::Protocol, "error"));
+
+        assert!(err.source().is_none());
+    }
+}
+
+#[cfg(feature = "websocket")]
+mod websocket {
+    use super::*;
+    use crate::transport::Transport;
+    use std::net::TcpListener;
+    use std::thread;
+    use tungstenite::Message;
+
+    #[test]
+    fn exchanges_messages_through_websocket() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind listener");
+        let addr = listener
+            .local_addr()
+            .expect("Could not get listener address");
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("Could not accept connection");
+            let mut socket = tungstenite::accept(stream).expect("Could not accept websocket");
+            let mut messages = vec![];
+
+            for reply in [
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"websocket\"]}]",
+                "[{\"channel\":\"/meta/connect\",\"successful\":true,\"clientId\":\"1234\"}]",
+            ] {
+                let message = socket.read().expect("Could not read message");
+
+                messages.push(message.into_text().expect("Message should be text"));
+                socket
+                    .send(Message::Text(reply.to_owned()))
+                    .expect("Could not send message");
+            }
+            messages
+        });
+        let mut client = Client::new(
+            &format!("http://{}/", addr),
+            VALID_ACCESS_TOKEN,
+            Duration::from_secs(5),
+        )
+        .expect("Could not build cometd client")
+        .set_transport(Transport::WebSocket);
+
+        client.init().expect("Could not init client");
+        assert_eq!(client.connect().expect("Could not connect").len(), 1);
+
+        let messages = server.join().expect("Server panicked");
+        assert_eq!(
+            messages[0],
+            "{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"supportedConnectionTypes\":[\"websocket\"],\"id\":\"1\"}"
+        );
+        assert_eq!(
+            messages[1],
+            "{\"channel\":\"/meta/connect\",\"clientId\":\"1234\",\"connectionType\":\"websocket\",\"id\":\"2\"}"
+        );
+    }
+
+    #[test]
+    fn keeps_messages_pushed_before_reply() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Could not bind listener");
+        let addr = listener
+            .local_addr()
+            .expect("Could not get listener address");
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("Could not accept connection");
+            let mut socket = tungstenite::accept(stream).expect("Could not accept websocket");
+            socket.read().expect("Could not read handshake");
+            socket
+                .send(Message::Text("[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"websocket\"],\"id\":\"1\"}]".to_owned()))
+                .expect("Could not send message");
+            socket.read().expect("Could not read subscribe");
+            // A delivery and the late reply to a former connect are pushed before the reply.
+            for frame in [
+                "[{\"channel\":\"/topic/a\",\"data\":{\"value\":1}}]",
+                "[{\"channel\":\"/meta/connect\",\"successful\":true,\"id\":\"0\"}]",
+                "[{\"channel\":\"/meta/subscribe\",\"successful\":true,\"subscription\":\"/topic/a\",\"id\":\"2\"}]",
+            ] {
+                socket
+                    .send(Message::Text(frame.to_owned()))
+                    .expect("Could not send message");
+            }
+            socket
+        });
+        let mut client = Client::new(
+            &format!("http://{}/", addr),
+            VALID_ACCESS_TOKEN,
+            Duration::from_secs(5),
+        )
+        .expect("Could not build cometd client")
+        .set_auto_disconnect(false)
+        .set_transport(Transport::WebSocket);
+
+        client.init().expect("Could not init client");
+        let resps = client.subscribe("/topic/a").expect("Could not subscribe");
+        let delivery = client
+            .messages()
+            .next()
+            .expect("The delivery should be kept")
+            .expect("Could not poll");
+
+        assert_eq!(resps.len(), 1);
+        assert_eq!(resps[0].channel(), "/meta/subscribe");
+        assert_eq!(delivery.channel(), "/topic/a");
+        server.join().expect("Server panicked");
+    }
+}
+
+mod channel {
+    use super::*;
+    use crate::channel::{self, validate_channel, ChannelKind};
+
+    #[test]
+    fn recognizes_meta_channels() {
+        assert!(channel::is_meta(channel::HANDSHAKE));
+        assert!(channel::is_meta("/meta"));
+        assert!(!channel::is_meta("/topic/meta"));
+        assert!(!channel::is_meta("/metadata/a"));
+    }
+
+    #[test]
+    fn accepts_valid_channels() {
+        assert!(validate_channel("/topic/a", ChannelKind::Publish).is_ok());
+        assert!(validate_channel("/topic/*", ChannelKind::Subscribe).is_ok());
+        assert!(validate_channel("/topic/**", ChannelKind::Subscribe).is_ok());
+        assert!(validate_channel("/meta/connect", ChannelKind::Subscribe).is_ok());
+    }
+
+    #[test]
+    fn rejects_empty_channel() {
+        let err = validate_channel("", ChannelKind::Subscribe).expect_err("Should be invalid");
+
+        assert_eq!(err.kind(), ErrorKind::Parse);
+    }
+
+    #[test]
+    fn rejects_channel_without_leading_slash() {
+        assert!(validate_channel("topic/a", ChannelKind::Subscribe).is_err());
+    }
+
+    #[test]
+    fn rejects_empty_segment() {
+        assert!(validate_channel("/topic//a", ChannelKind::Subscribe).is_err());
+        assert!(validate_channel("/topic/", ChannelKind::Publish).is_err());
+    }
+
+    #[test]
+    fn rejects_publishing_on_meta_channel() {
+        assert!(validate_channel("/meta/connect", ChannelKind::Publish).is_err());
+    }
+
+    #[test]
+    fn rejects_publishing_on_wildcard_channel() {
+        assert!(validate_channel("/topic/*", ChannelKind::Publish).is_err());
+    }
+
+    #[test]
+    fn rejects_misplaced_wildcard() {
+        assert!(validate_channel("/topic/*/a", ChannelKind::Subscribe).is_err());
+        assert!(validate_channel("/topic/a*", ChannelKind::Subscribe).is_err());
+    }
+
+    #[test]
+    fn is_checked_before_sending() {
+        let m = mock("POST", "/").expect(0).create();
+        let mut client = client();
+        let err = client
+            .publish("/meta/handshake", "hello")
+            .expect_err("Publish should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Parse);
+        m.assert();
+    }
+}
+
+mod subscribe {
+    use super::*;
+    use crate::response::{Response, Subscription};
+
+    fn init_client() -> (Client, mockito::Mock) {
+        let m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"1234\",\"supportedConnectionTypes\":[\"long-polling\"]}]",
+            )
+            .create();
+        let mut client = client();
+
+        client.init().expect("Could not init client");
+        (client, m)
+    }
+
+    #[test]
+    fn resubscribes_on_manual_rehandshake() {
+        let (mut client, hs_mock) = init_client();
+        let subscribe_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/subscribe\",\"subscription\":\"/topic/a\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/subscribe\",\"successful\":true}]")
+            .expect(2)
+            .create();
+
+        client.subscribe("/topic/a").expect("Could not subscribe");
+        drop(hs_mock);
+        let _hs_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"successful\":true,\"clientId\":\"5678\",\"supportedConnectionTypes\":[\"long-polling\"]}]")
+            .create();
+        client.rehandshake().expect("Could not handshake again");
+
+        assert_eq!(client.client_id(), Some("5678"));
+        subscribe_mock.assert();
+    }
+
+    #[test]
+    fn queues_subscriptions_until_init() {
+        let _hs_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let subscribe_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/subscribe\",\"subscription\":\"/topic/a\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/subscribe\",\"successful\":true}]")
+            .expect(1)
+            .create();
+        let mut client = client();
+
+        assert!(client
+            .subscribe("/topic/a")
+            .expect("Could not queue subscription")
+            .is_empty());
+        assert!(!subscribe_mock.matched());
+        client.init().expect("Could not init client");
+        subscribe_mock.assert();
+    }
+
+    #[test]
+    fn keeps_queued_subscriptions_after_failed_one() {
+        let _hs_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _rejected_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/subscribe\",\"subscription\":\"/topic/a\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/subscribe\",\"successful\":false,\"error\":\"403::Forbidden\"}]")
+            .create();
+        let subscribe_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/subscribe\",\"subscription\":\"/topic/b\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/subscribe\",\"successful\":true}]")
+            .expect(1)
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+
+        client
+            .subscribe("/topic/a")
+            .expect("Could not queue subscription");
+        client
+            .subscribe("/topic/b")
+            .expect("Could not queue subscription");
+        client.init().expect_err("Init should not return Ok");
+        assert!(!subscribe_mock.matched());
+        client.init().expect("Could not init client");
+        subscribe_mock.assert();
+    }
+
+    #[test]
+    fn subscribes_again_on_retry_advice() {
+        let (mut client, _hs_mock) = init_client();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .expect(0)
+            .create();
+        let subscribe_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/subscribe\",\"subscription\":\"/topic/a\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"advice\":{\"reconnect\":\"retry\",\"interval\":0},\"channel\":\"/meta/subscribe\",\"error\":\"400::Error\",\"successful\":false}]")
+            .expect(RETRIES_MAX as usize + 1)
+            .create();
+        let err = client
+            .subscribe("/topic/a")
+            .expect_err("Subscribe should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::MaxRetriesReached);
+        subscribe_mock.assert();
+        connect_mock.assert();
+    }
+
+    #[test]
+    fn errors_when_not_accepted() {
+        let (mut client, _hs_mock) = init_client();
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/subscribe\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/subscribe\",\"successful\":false,\"subscription\":\"/topic/a\"}]")
+            .create();
+        let err = client
+            .subscribe("/topic/a")
+            .expect_err("Subscribe should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Protocol);
+        assert!(client
+            .unsubscribe_all()
+            .expect("Could not unsubscribe")
+            .is_empty());
+    }
+
+    #[test]
+    fn resubscribes_after_handshake_advice() {
+        let (mut client, _hs_mock) = init_client();
+        let subscribe_mocks = ["/topic/a", "/topic/b"]
+            .iter()
+            .map(|channel| {
+                mock("POST", "/")
+                    .match_body(Matcher::PartialJson(serde_json::json!({
+                        "channel": "/meta/subscribe",
+                        "subscription": channel,
+                    })))
+                    .with_status(200)
+                    .with_body("[{\"channel\":\"/meta/subscribe\",\"successful\":true}]")
+                    .expect(2)
+                    .create()
+            })
+            .collect::<Vec<_>>();
+        let unsubscribe_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/unsubscribe\",\"subscription\":\"/topic/c\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/unsubscribe\",\"successful\":true}]")
+            .create();
+        let subscribe_c_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/subscribe\",\"subscription\":\"/topic/c\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/subscribe\",\"successful\":true}]")
+            .expect(1)
+            .create();
+        let _connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"advice\":{\"reconnect\":\"handshake\"},\"channel\":\"/meta/connect\",\"successful\":false,\"error\":\"402::Unknown client\"}]")
+            .create();
+
+        client.subscribe("/topic/a").expect("Could not subscribe");
+        client.subscribe("/topic/b").expect("Could not subscribe");
+        client.subscribe("/topic/c").expect("Could not subscribe");
+        client
+            .unsubscribe("/topic/c")
+            .expect("Could not unsubscribe");
+        let mut client = client.set_advice_reconnects(1);
+        client.connect().expect_err("Connect should not return Ok");
+
+        for m in subscribe_mocks.iter() {
+            m.assert();
+        }
+        subscribe_c_mock.assert();
+        unsubscribe_mock.assert();
+    }
+
+    #[test]
+    fn subscribes_to_many_with_combined_response() {
+        let (mut client, _hs_mock) = init_client();
+        let m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/subscribe\",\"subscription\":[\"/topic/a\",\"/topic/b\"]}"
+                    .to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/subscribe\",\"successful\":true,\"subscription\":[\"/topic/a\",\"/topic/b\"]}]")
+            .create();
+        let resps = client
+            .subscribe_many(&["/topic/a", "/topic/b"])
+            .expect("Could not subscribe");
+
+        m.assert();
+        assert_eq!(resps.len(), 1);
+        match &resps[0] {
+            Response::Basic(resp) => assert_eq!(
+                resp.subscription,
+                Some(Subscription::Many(vec![
+                    "/topic/a".to_owned(),
+                    "/topic/b".to_owned()
+                ]))
+            ),
+            resp => panic!("Unexpected response {:?}", resp),
+        }
+    }
+
+    #[test]
+    fn subscribes_to_many_with_per_channel_responses() {
+        let (mut client, _hs_mock) = init_client();
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/subscribe\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/subscribe\",\"successful\":true,\"subscription\":\"/topic/a\"},{\"channel\":\"/meta/subscribe\",\"successful\":true,\"subscription\":\"/topic/b\"}]")
+            .create();
+        let resps = client
+            .subscribe_many(&["/topic/a", "/topic/b"])
+            .expect("Could not subscribe");
+
+        assert_eq!(resps.len(), 2);
+    }
+}
+mod unsubscribe {
+    use super::*;
+
+    #[test]
+    fn unsubscribes_from_all_channels() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _subscribe_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/subscribe\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/subscribe\",\"successful\":true}]")
+            .create();
+        let unsubscribe_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "channel": "/meta/unsubscribe",
+                "subscription": ["/topic/a", "/topic/b"],
+            })))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/unsubscribe\",\"successful\":true}]")
+            .expect(1)
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+
+        client.init().expect("Could not init client");
+        client.subscribe("/topic/a").expect("Could not subscribe");
+        client.subscribe("/topic/b").expect("Could not subscribe");
+        client.unsubscribe_all().expect("Could not unsubscribe");
+        client.unsubscribe_all().expect("Could not unsubscribe");
+        unsubscribe_mock.assert();
+    }
+}
+
+mod publish {
+    use super::*;
+    use crate::response::Response;
+
+    #[test]
+    fn publishes_again_on_retry_advice() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .expect(0)
+            .create();
+        let publish_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "channel": "/topic/a",
+                "data": { "price": 12 },
+            })))
+            .with_status(200)
+            .with_body("[{\"advice\":{\"reconnect\":\"retry\",\"interval\":0},\"channel\":\"/topic/a\",\"error\":\"400::Error\",\"successful\":false}]")
+            .expect(RETRIES_MAX as usize + 1)
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+
+        client.init().expect("Could not init client");
+        let err = client
+            .publish("/topic/a", serde_json::json!({ "price": 12 }))
+            .expect_err("Publish should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::MaxRetriesReached);
+        publish_mock.assert();
+        connect_mock.assert();
+    }
+
+    #[test]
+    fn keeps_acknowledgement_among_deliveries() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _publish_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/topic/a\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/topic/b\",\"data\":{\"value\":1}},{\"channel\":\"/topic/a\",\"data\":{\"value\":2}},{\"channel\":\"/topic/a\",\"successful\":true,\"id\":\"2\"}]")
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+
+        client.init().expect("Could not init client");
+        let resps = client
+            .publish("/topic/a", serde_json::json!({ "value": 2 }))
+            .expect("Could not publish");
+        let channels = resps.iter().map(Response::channel).collect::<Vec<_>>();
+        let acks = resps
+            .iter()
+            .filter(|resp| resp.matches_channel("/topic/a") && resp.is_successful().is_some())
+            .collect::<Vec<_>>();
+
+        assert_eq!(channels, vec!["/topic/b", "/topic/a", "/topic/a"]);
+        assert_eq!(acks.len(), 1);
+        assert_eq!(acks[0].is_successful(), Some(true));
+        assert_eq!(acks[0].id(), Some("2"));
+    }
+
+    #[test]
+    fn sends_ext() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let publish_mock = mock("POST", "/")
+            .match_body(Matcher::Json(serde_json::json!({
+                "channel": "/topic/a",
+                "clientId": "1234",
+                "data": "hello",
+                "id": "2",
+                "ext": {"replay": {"/topic/a": -1}},
+            })))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/topic/a\",\"successful\":true}]")
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+
+        client.init().expect("Could not init client");
+        client
+            .publish_with_ext(
+                "/topic/a",
+                "hello",
+                serde_json::json!({"replay": {"/topic/a": -1}}),
+            )
+            .expect("Could not publish");
+        publish_mock.assert();
+    }
+
+    #[test]
+    fn publishes_batch_in_one_request() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let publish_mock = mock("POST", "/")
+            .match_body(Matcher::Json(serde_json::json!([
+                {"channel": "/topic/a", "clientId": "1234", "data": 1, "id": "2"},
+                {"channel": "/topic/b", "clientId": "1234", "data": 2, "id": "3"},
+                {"channel": "/topic/a", "clientId": "1234", "data": 3, "id": "4"},
+            ])))
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/topic/a\",\"successful\":true,\"id\":\"2\"},\
+                 {\"channel\":\"/topic/b\",\"successful\":true,\"id\":\"3\"},\
+                 {\"channel\":\"/topic/a\",\"successful\":true,\"id\":\"4\"}]",
+            )
+            .expect(1)
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+
+        client.init().expect("Could not init client");
+        let resps = client
+            .publish_batch(&[
+                ("/topic/a", serde_json::json!(1)),
+                ("/topic/b", serde_json::json!(2)),
+                ("/topic/a", serde_json::json!(3)),
+            ])
+            .expect("Could not publish");
+
+        assert_eq!(
+            resps.iter().filter_map(Response::id).collect::<Vec<_>>(),
+            vec!["2", "3", "4"]
+        );
+        publish_mock.assert();
+    }
+
+    #[test]
+    fn returns_partial_results() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _publish_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "[{\"channel\":\"/topic/a\"},{\"channel\":\"/topic/b\"}]".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/topic/a\",\"successful\":true,\"id\":\"2\"},\
+                 {\"channel\":\"/topic/b\",\"successful\":false,\"error\":\"403::Forbidden\",\"id\":\"3\"}]",
+            )
+            .create();
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .set_partial_results(true);
+
+        client.init().expect("Could not init client");
+        let resps = client
+            .publish_batch(&[
+                ("/topic/a", serde_json::json!(1)),
+                ("/topic/b", serde_json::json!(2)),
+            ])
+            .expect("Could not publish");
+        let errors = client.take_partial_errors();
+
+        assert_eq!(resps.len(), 1);
+        assert_eq!(resps[0].channel(), "/topic/a");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind(), ErrorKind::Protocol);
+        assert_eq!(errors[0].message, "403::Forbidden");
+        assert!(client.take_partial_errors().is_empty());
+    }
+
+    #[test]
+    fn fails_rejected_subscription_with_partial_results() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _subscribe_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/subscribe\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/meta/subscribe\",\"successful\":false,\"error\":\"403::Forbidden\",\"subscription\":\"/topic/a\",\"id\":\"2\"}]",
+            )
+            .create();
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .set_partial_results(true);
+
+        client.init().expect("Could not init client");
+        let err = client
+            .subscribe("/topic/a")
+            .expect_err("Subscribe should not return Ok");
+
+        assert_eq!(err.kind(), ErrorKind::Protocol);
+        // Nothing to unsubscribe from, so no request is sent.
+        assert!(client
+            .unsubscribe_all()
+            .expect("Could not unsubscribe")
+            .is_empty());
+        assert!(client.take_partial_errors().is_empty());
+    }
+
+    #[test]
+    fn waits_for_reply_in_publish_response() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _publish_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/service/request\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(
+                "[{\"channel\":\"/service/request\",\"successful\":true,\"id\":\"2\"},\
+                 {\"channel\":\"/topic/other\",\"data\":1},\
+                 {\"channel\":\"/service/reply\",\"data\":{\"answer\":42}}]",
+            )
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+
+        client.init().expect("Could not init client");
+        let reply = client
+            .publish_and_wait(
+                "/service/request",
+                "question",
+                "/service/reply",
+                Duration::from_secs(5),
+            )
+            .expect("Could not get reply");
+        let buffered = client
+            .messages()
+            .next()
+            .expect("Should have buffered a message")
+            .expect("Could not get message");
+
+        assert_eq!(reply.channel(), "/service/reply");
+        assert_eq!(buffered.channel(), "/topic/other");
+    }
+
+    #[test]
+    fn waits_for_reply_delivered_on_connect() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let _publish_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/service/request\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/service/request\",\"successful\":true,\"id\":\"2\"}]")
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/connect\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/service/reply\",\"data\":{\"answer\":42}},{\"channel\":\"/meta/connect\",\"successful\":true}]")
+            .expect(1)
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+
+        client.init().expect("Could not init client");
+        let reply = client
+            .publish_and_wait(
+                "/service/request",
+                "question",
+                "/service/reply",
+                Duration::from_secs(5),
+            )
+            .expect("Could not get reply");
+
+        match reply {
+            Response::Delivery(delivery) => {
+                assert_eq!(delivery.data, serde_json::json!({"answer": 42}))
+            }
+            resp => panic!("Unexpected response {:?}", resp),
+        }
+        connect_mock.assert();
+    }
+
+    #[test]
+    fn uses_custom_serializer() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let publish_mock = mock("POST", "/")
+            .match_header("content-type", "application/json")
+            .match_body(Matcher::Regex("\n  \"channel\": \"/topic/a\"".to_owned()))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/topic/a\",\"successful\":true}]")
+            .create();
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .set_serializer(Box::new(|value| Ok(serde_json::to_string_pretty(value)?)));
+
+        client.init().expect("Could not init client");
+        client
+            .publish("/topic/a", "hello")
+            .expect("Could not publish");
+        publish_mock.assert();
+    }
+
+    #[test]
+    fn adds_provided_ext() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let connect_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "channel": "/meta/connect",
+                "ext": { "kind": "connect" },
+            })))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/meta/connect\",\"successful\":true}]")
+            .create();
+        let publish_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJson(serde_json::json!({
+                "channel": "/topic/a",
+                "ext": { "kind": "publish", "replay": 12 },
+            })))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/topic/a\",\"successful\":true}]")
+            .create();
+        let mut client = client()
+            .set_auto_disconnect(false)
+            .set_ext_provider(Box::new(|channel| match channel {
+                "/meta/connect" => Some(serde_json::json!({ "kind": "connect" })),
+                "/topic/a" => Some(serde_json::json!({ "kind": "publish" })),
+                _ => None,
+            }));
+
+        client.init().expect("Could not init client");
+        client.connect().expect("Could not connect");
+        client
+            .publish_with_ext("/topic/a", "hello", serde_json::json!({ "replay": 12 }))
+            .expect("Could not publish");
+        connect_mock.assert();
+        publish_mock.assert();
+    }
+
+    #[test]
+    fn records_requests_in_dry_run() {
+        let mut client = Client::new("http://127.0.0.1:1", "1234", Duration::from_secs(1))
+            .expect("Could not build cometd client")
+            .set_auto_disconnect(false)
+            .set_dry_run(true);
+
+        client.init().expect("Could not init client");
+        client.subscribe("/topic/a").expect("Could not subscribe");
+        client
+            .publish("/topic/a", "hello")
+            .expect("Could not publish");
+        let requests = client.take_dry_run_requests();
+
+        assert_eq!(client.client_id(), Some("dry-run"));
+        assert_eq!(requests.len(), 3);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&requests[2]).expect("Invalid request"),
+            serde_json::json!({
+                "channel": "/topic/a",
+                "clientId": "dry-run",
+                "data": "hello",
+                "id": "3",
+            })
+        );
+        assert!(client.take_dry_run_requests().is_empty());
+    }
+
+    #[test]
+    fn serializes_default_handshake_and_connect() {
+        let mut client = Client::new("http://127.0.0.1:1", "1234", Duration::from_secs(1))
+            .expect("Could not build cometd client")
+            .set_auto_disconnect(false)
+            .set_dry_run(true);
+
+        client.init().expect("Could not init client");
+        client.connect().expect("Could not connect");
+        let requests = client.take_dry_run_requests();
+
+        assert_eq!(
+            requests,
+            vec![
+                "{\"channel\":\"/meta/handshake\",\"version\":\"1.0\",\"supportedConnectionTypes\":[\"long-polling\"],\"id\":\"1\"}",
+                "{\"channel\":\"/meta/connect\",\"clientId\":\"dry-run\",\"connectionType\":\"long-polling\",\"id\":\"2\"}",
+            ]
+        );
+    }
+
+    #[test]
+    fn returns_message_id_when_tracked() {
+        let _m = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/meta/handshake\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body(HANDSHAKE_RESPONSE)
+            .create();
+        let publish_mock = mock("POST", "/")
+            .match_body(Matcher::PartialJsonString(
+                "{\"channel\":\"/topic/a\",\"id\":\"2\"}".to_owned(),
+            ))
+            .with_status(200)
+            .with_body("[{\"channel\":\"/topic/a\",\"successful\":true,\"id\":\"2\"}]")
+            .create();
+        let mut client = client().set_auto_disconnect(false);
+
+        client.init().expect("Could not init client");
+        let id = client
+            .publish_tracked("/topic/a", "hello")
+            .expect("Could not publish");
+
+        assert_eq!(id, "2");
+        publish_mock.assert();
+    }
+}
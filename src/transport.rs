@@ -0,0 +1,60 @@
+#[cfg(feature = "websocket")]
+pub(crate) mod websocket;
+
+use crate::error::Error;
+
+/// The transport used to exchange messages with the cometd server.
+#[derive(PartialEq, Debug, Clone, Copy, Default)]
+pub enum Transport {
+    /// Messages are sent through HTTP POST requests. This is the default.
+    #[default]
+    LongPolling,
+    /// Messages are exchanged through a single WebSocket connection. It requires the
+    /// `websocket` feature, without which building a client using it fails.
+    WebSocket,
+}
+
+impl Transport {
+    /// Returns the connection type advertised to the server for this transport.
+    pub fn connection_type(&self) -> &'static str {
+        match self {
+            Transport::LongPolling => "long-polling",
+            Transport::WebSocket => "websocket",
+        }
+    }
+
+    /// Checks that messages of a connection type can be exchanged through the transport: a
+    /// WebSocket only carries the `websocket` type, and HTTP requests every other one.
+    ///
+    /// # Errors
+    ///
+    /// The transport cannot carry the connection type.
+    pub(crate) fn check_carries(&self, connection_type: &str) -> Result<(), Error> {
+        if (connection_type == "websocket") == (*self == Transport::WebSocket) {
+            return Ok(());
+        }
+        let message = format!(
+            "The {} transport cannot carry the {} connection type",
+            self.connection_type(),
+            connection_type
+        );
+
+        Err(Error::new(crate::error::ErrorKind::Transport, &message))
+    }
+
+    /// Checks that the transport was compiled in.
+    ///
+    /// # Errors
+    ///
+    /// The transport requires a feature which is not enabled.
+    pub(crate) fn check_enabled(&self) -> Result<(), Error> {
+        match self {
+            #[cfg(not(feature = "websocket"))]
+            Transport::WebSocket => Err(Error::new(
+                crate::error::ErrorKind::Transport,
+                "The websocket transport requires the websocket feature",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use reqwest::{header::HeaderMap, Url};
+use std::net::TcpStream;
+use std::time::Duration;
+use tungstenite::{client::IntoClientRequest, stream::MaybeTlsStream, Message};
+
+use crate::error::{Error, ErrorKind};
+
+pub(crate) type WebSocket = tungstenite::WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// Opens a WebSocket connection to the cometd server. The headers are sent along with
+/// the upgrade request so the authentication and cookies are the same as over HTTP.
+pub(crate) fn connect_websocket(
+    base_url: &Url,
+    headers: &HeaderMap,
+    timeout: Option<Duration>,
+) -> Result<WebSocket, Error> {
+    let mut url = base_url.clone();
+    let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+
+    url.set_scheme(scheme)
+        .map_err(|_| Error::new(ErrorKind::Parse, "Could not build websocket url"))?;
+    let mut req = url.as_str().into_client_request().map_err(|err| {
+        Error::with_source(ErrorKind::Parse, "Could not build websocket request", err)
+    })?;
+    for (name, value) in headers.iter() {
+        if let (Ok(name), Ok(value)) = (
+            tungstenite::http::HeaderName::from_bytes(name.as_str().as_bytes()),
+            tungstenite::http::HeaderValue::from_bytes(value.as_bytes()),
+        ) {
+            req.headers_mut().insert(name, value);
+        }
+    }
+
+    let (socket, _) = tungstenite::connect(req)
+        .map_err(|err| Error::with_source(ErrorKind::Transport, "Could not open websocket", err))?;
+    let stream = match socket.get_ref() {
+        MaybeTlsStream::Plain(stream) => Some(stream),
+        MaybeTlsStream::NativeTls(stream) => Some(stream.get_ref()),
+        _ => None,
+    };
+    if let Some(stream) = stream {
+        stream.set_read_timeout(timeout).map_err(|err| {
+            Error::with_source(ErrorKind::Transport, "Could not set websocket timeout", err)
+        })?;
+    }
+
+    log::debug!("Successfully opened websocket to {}", url);
+    Ok(socket)
+}
+
+/// Returns the messages of a text frame, be it an array or a single message.
+fn messages(text: &str) -> Vec<serde_json::Value> {
+    match serde_json::from_str::<serde_json::Value>(text) {
+        Ok(serde_json::Value::Array(messages)) => messages,
+        Ok(message) => vec![message],
+        Err(_) => vec![],
+    }
+}
+
+/// Returns whether a frame carries the reply to one of the messages sent. A reply is
+/// recognized by its id, or by its channel for servers not sending the ids back.
+fn is_reply(frame: &[serde_json::Value], sent: &[serde_json::Value]) -> bool {
+    frame.iter().any(|message| {
+        sent.iter().any(|sent| match message.get("id") {
+            Some(id) => Some(id) == sent.get("id"),
+            None => message.get("successful").is_some() && message["channel"] == sent["channel"],
+        })
+    })
+}
+
+/// Sends a message through the WebSocket and returns the text frame carrying the reply to
+/// it. Since the server pushes messages at any time, the frames read before the reply are
+/// kept in `unrelated` rather than mistaken for it.
+pub(crate) fn exchange(
+    socket: &mut WebSocket,
+    body: String,
+    unrelated: &mut Vec<String>,
+) -> Result<String, Error> {
+    let sent = messages(&body);
+
+    socket.send(Message::Text(body)).map_err(|err| {
+        Error::with_source(
+            ErrorKind::Transport,
+            "Could not send websocket message",
+            err,
+        )
+    })?;
+
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                if sent.is_empty() || is_reply(&messages(&text), &sent) {
+                    return Ok(text);
+                }
+                unrelated.push(text);
+            }
+            Ok(Message::Close(_)) => {
+                return Err(Error::new(
+                    ErrorKind::Transport,
+                    "Websocket closed by the server",
+                ))
+            }
+            Ok(_) => continue,
+            Err(err) => {
+                return Err(Error::with_source(
+                    ErrorKind::Transport,
+                    "Could not read websocket message",
+                    err,
+                ))
+            }
+        }
+    }
+}